@@ -1,16 +1,47 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dmtrd::{
-    domain::{Config, Domain},
-    driven::{event_dispatch::EventDispatch, fabric_state::FabricState},
+    domain::{Config, Domain, KindDefinition, KindRegistry},
+    driven::{
+        event_dispatch::{new_receipt, EventDispatch},
+        fabric_state::FabricState,
+    },
 };
 use serde::Deserialize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::info;
+use tracing::{error, info};
 
 #[derive(Parser)]
 #[clap(name = "Demeter Operator", version = "")]
-struct App {}
+struct App {
+    /// Directory with the definitions of the supported resource kinds
+    #[arg(long)]
+    kinds_dir: Option<std::path::PathBuf>,
+
+    /// File where dispatched events are logged, events are kept in memory only
+    /// if missing
+    #[arg(long)]
+    event_log: Option<std::path::PathBuf>,
+
+    /// File where the fabric state is kept, it's kept in memory only and
+    /// seeded with dummy data if missing
+    #[arg(long)]
+    fabric_state: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Applies the logged events that the fabric state hasn't caught up with
+    Replay {
+        /// Drop the projections and rebuild them from the start of the log,
+        /// refused when the log doesn't account for all of their data
+        #[arg(long)]
+        rebuild: bool,
+    },
+}
 
 #[derive(Deserialize, Debug)]
 struct ConfigRoot {}
@@ -42,21 +73,29 @@ async fn seed_dummy_data(domain: &mut Domain) {
                 root_public_key: "123".into(),
             }
             .into(),
+            new_receipt(),
         )
         .await
         .unwrap();
 
     let pwd = hex::decode("6d7962616470617373776f7264").unwrap();
-    let salt = b"somesaltforyou";
+    let salt = dmtrd::domain::random_salt();
+    let hash_params = dmtrd::domain::HashParams::default();
 
     domain
         .handle(
             dmtrd::domain::ApiKeyRegisteredV1 {
                 namespace: "ns1".into(),
-                digest: dmtrd::domain::digest(&pwd, salt).unwrap(),
-                salt: salt.to_vec(),
+                key_id: b"devkey01".to_vec(),
+                digest: dmtrd::domain::digest(&pwd, &salt, &hash_params).unwrap(),
+                salt,
+                hash_params,
+                scopes: dmtrd::domain::Scope::ALL.to_vec(),
+                not_before: None,
+                not_after: None,
             }
             .into(),
+            new_receipt(),
         )
         .await
         .unwrap();
@@ -64,22 +103,78 @@ async fn seed_dummy_data(domain: &mut Domain) {
 
 #[tokio::main]
 async fn main() {
-    let _ = App::parse();
+    let app = App::parse();
 
     tracing_subscriber::fmt::init();
 
-    let fabric_state = FabricState::ephemeral().await.unwrap();
-    let event_dispatch = EventDispatch::ephemeral(100);
+    let kinds = match &app.kinds_dir {
+        Some(dir) => KindRegistry::from_dir(dir).unwrap(),
+        None => KindRegistry::new(vec![KindDefinition {
+            kind: "workers.demeter.run/v1alpha1".into(),
+            schema: serde_json::json!({ "type": "object" }),
+        }])
+        .unwrap(),
+    };
+
+    let fabric_state = match &app.fabric_state {
+        Some(path) => {
+            let state = FabricState::open(path).await.unwrap();
+            state.migrate().await.unwrap();
+            state
+        }
+        None => FabricState::ephemeral().await.unwrap(),
+    };
+    let event_dispatch = match &app.event_log {
+        Some(path) => EventDispatch::persistent(path, 100).await.unwrap(),
+        None => EventDispatch::ephemeral(100),
+    };
 
     let mut domain = Domain {
         config: Config {
             cluster: b"123".into(),
+            apikey_hashing: Default::default(),
+            apikey_rotation_grace: 24 * 3600,
+            resource_finalizers: vec![
+                "k8s.demeter.run/teardown".into(),
+                "accounting.demeter.run/usage".into(),
+            ],
+            kinds,
         },
         fabric_state,
         event_dispatch,
+        pending_revisions: Default::default(),
     };
 
-    seed_dummy_data(&mut domain).await;
+    if let Some(Command::Replay { rebuild }) = app.command {
+        assert!(app.event_log.is_some(), "replay needs an --event-log");
+
+        let result = match rebuild {
+            true => domain.rebuild_projections().await,
+            false => domain.catch_up().await,
+        };
+
+        match result {
+            Ok(sequence) => info!(sequence, "fabric state caught up with the event log"),
+            Err(error) => error!(?error, "failed to replay the event log"),
+        }
+
+        return;
+    }
+
+    let subscription = domain.event_dispatch.subscribe();
+
+    // the monitor catches up again before applying anything, so a failure here
+    // doesn't have to stop the daemon
+    if app.event_log.is_some() {
+        match domain.catch_up().await {
+            Ok(sequence) => info!(sequence, "fabric state caught up with the event log"),
+            Err(error) => error!(?error, "failed to catch up with the event log"),
+        }
+    }
+
+    if app.fabric_state.is_none() {
+        seed_dummy_data(&mut domain).await;
+    }
 
     let domain = Arc::new(Mutex::new(domain));
 
@@ -90,6 +185,7 @@ async fn main() {
         dmtrd::drivers::rpc::serve(
             dmtrd::drivers::rpc::Config {
                 listen_address: "[::]:50051".into(),
+                auth_throttle: Default::default(),
             },
             domain1,
         )
@@ -100,7 +196,7 @@ async fn main() {
     let thread2 = tokio::spawn(async move {
         info!("starting fabric monitor");
 
-        dmtrd::drivers::fabric_monitor::run(domain2).await
+        dmtrd::drivers::fabric_monitor::run(domain2, subscription).await
     });
 
     let (res1, res2) = tokio::try_join!(thread1, thread2).unwrap();
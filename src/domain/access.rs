@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use super::{parse_scopes, ApiKeyId, MemberKey, PermissionDenied, Scope};
+
+/// Roles that can be granted to members of a namespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    Owner,
+    Admin,
+    Developer,
+    Viewer,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Owner, Role::Admin, Role::Developer, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Developer => "developer",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn scopes(&self) -> &'static [Scope] {
+        match self {
+            Role::Owner | Role::Admin => &Scope::ALL,
+            Role::Developer => &[Scope::ResourcesRead, Scope::ResourcesWrite],
+            Role::Viewer => &[Scope::ResourcesRead],
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Role::ALL
+            .into_iter()
+            .find(|x| x.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown role {s}"))
+    }
+}
+
+/// The identity behind a credential once it has been verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    /// Holder of the namespace root key
+    RootOwner,
+    Member(MemberKey, Role),
+    /// Api key with its stored scopes, `None` for unrestricted legacy keys
+    ApiKey(Option<ApiKeyId>, Option<String>),
+}
+
+impl Principal {
+    pub fn is_owner(&self) -> bool {
+        matches!(
+            self,
+            Principal::RootOwner | Principal::Member(_, Role::Owner)
+        )
+    }
+
+    pub fn authorize(&self, scope: Scope) -> Result<()> {
+        let allowed = match self {
+            Principal::RootOwner => true,
+            Principal::Member(_, role) => role.scopes().contains(&scope),
+            Principal::ApiKey(_, None) => true,
+            Principal::ApiKey(_, Some(scopes)) => parse_scopes(scopes)?.contains(&scope),
+        };
+
+        if !allowed {
+            let err = anyhow!("principal lacks the {} scope", scope.as_str());
+            return Err(PermissionDenied(err).into());
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the principal holds every one of the given scopes, so that
+    /// it can't grant more than it has
+    pub fn authorize_all(&self, scopes: &[Scope]) -> Result<()> {
+        scopes.iter().try_for_each(|x| self.authorize(*x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_grant_expected_scopes() {
+        let viewer = Principal::Member(b"key".to_vec(), Role::Viewer);
+        assert!(viewer.authorize(Scope::ResourcesRead).is_ok());
+        assert!(viewer.authorize(Scope::ResourcesWrite).is_err());
+        assert!(viewer.authorize(Scope::MembersWrite).is_err());
+
+        let developer = Principal::Member(b"key".to_vec(), Role::Developer);
+        assert!(developer.authorize(Scope::ResourcesWrite).is_ok());
+        assert!(developer.authorize(Scope::BillingRead).is_err());
+
+        let admin = Principal::Member(b"key".to_vec(), Role::Admin);
+        assert!(admin.authorize(Scope::MembersWrite).is_ok());
+        assert!(!admin.is_owner());
+
+        assert!(Principal::RootOwner.is_owner());
+        assert_eq!("developer".parse::<Role>().unwrap(), Role::Developer);
+    }
+
+    #[test]
+    fn api_keys_are_limited_to_their_scopes() {
+        let key = Principal::ApiKey(Some(b"id".to_vec()), Some("billing:read".into()));
+        assert!(key.authorize(Scope::BillingRead).is_ok());
+        assert!(key
+            .authorize(Scope::ResourcesRead)
+            .unwrap_err()
+            .is::<PermissionDenied>());
+
+        let legacy = Principal::ApiKey(None, None);
+        assert!(legacy.authorize(Scope::ResourcesWrite).is_ok());
+
+        let narrow = Principal::ApiKey(Some(b"id".to_vec()), Some("apikeys:write".into()));
+        assert!(narrow.authorize_all(&[Scope::ApiKeysWrite]).is_ok());
+        assert!(narrow
+            .authorize_all(&[Scope::ApiKeysWrite, Scope::BillingRead])
+            .is_err());
+    }
+}
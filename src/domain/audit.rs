@@ -0,0 +1,70 @@
+use anyhow::Result;
+
+use super::{unix_now, Credential, NamespaceName};
+use crate::driven::fabric_state::AuditRecord;
+
+impl Credential {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Credential::OwnerSignatureV1(..) => "owner_signature",
+            Credential::MemberSignatureV1(..) => "member_signature",
+            Credential::ApiKeyV1(..) => "legacy_api_key",
+            Credential::ApiKeyV2(..) => "api_key",
+        }
+    }
+
+    /// Identifies the key behind the credential: the key id for api keys and
+    /// the public key for member signatures
+    pub fn key_id(&self) -> Option<Vec<u8>> {
+        match self {
+            Credential::ApiKeyV2(key_id, _) => Some(key_id.clone()),
+            Credential::MemberSignatureV1(public_key, ..) => Some(public_key.clone()),
+            Credential::OwnerSignatureV1(..) | Credential::ApiKeyV1(..) => None,
+        }
+    }
+}
+
+/// What gets recorded about a command or query before it executes
+pub struct AuditTrail {
+    operation: &'static str,
+    namespace: NamespaceName,
+    credential_kind: &'static str,
+    key_id: Option<Vec<u8>>,
+    resource_uuid: Option<Vec<u8>>,
+}
+
+impl AuditTrail {
+    pub fn new(operation: &'static str, namespace: &str, credential: &Credential) -> Self {
+        Self {
+            operation,
+            namespace: namespace.to_owned(),
+            credential_kind: credential.kind(),
+            key_id: credential.key_id(),
+            resource_uuid: None,
+        }
+    }
+
+    /// Records the resource the operation acts on, as soon as it's resolved so
+    /// that failed attempts still carry it
+    pub fn set_resource_uuid(&mut self, uuid: &[u8]) {
+        self.resource_uuid = Some(uuid.to_vec());
+    }
+
+    pub fn into_record<T>(self, result: &Result<T>) -> AuditRecord {
+        let (outcome, error) = match result {
+            Ok(_) => ("success", None),
+            Err(err) => ("failure", Some(err.to_string())),
+        };
+
+        AuditRecord {
+            timestamp: unix_now().unwrap_or_default() as i64,
+            namespace: self.namespace,
+            credential_kind: self.credential_kind.into(),
+            key_id: self.key_id,
+            operation: self.operation.into(),
+            resource_uuid: self.resource_uuid,
+            outcome: outcome.into(),
+            error,
+        }
+    }
+}
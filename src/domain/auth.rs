@@ -1,10 +1,159 @@
-use anyhow::{anyhow, Context, Result};
-use argon2::Argon2;
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
 
-use super::HashDigest;
+use super::{ApiKeyId, AuthTimestamp, HashDigest, HashSalt, SignatureValue, Timestamp};
+use crate::driven::fabric_state::ApiKeyHashing;
 
-pub fn digest(pwd: &[u8], salt: &[u8]) -> Result<HashDigest> {
-    let argon2 = Argon2::default();
+/// Max distance (in seconds) between an owner signature timestamp and the
+/// local clock before the signature is considered stale
+pub const MAX_SIGNATURE_SKEW: u64 = 300;
+
+pub const SALT_LENGTH: usize = 16;
+
+pub const KEY_ID_LENGTH: usize = 8;
+
+/// Argon2 parameters used to compute an api key digest
+///
+/// These are stored next to each digest so that costs can be raised for new
+/// keys without invalidating the ones already registered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashParams {
+    pub algorithm: String,
+    pub version: u32,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Argon2id.as_str().to_owned(),
+            version: Version::V0x13 as u32,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl From<ApiKeyHashing> for HashParams {
+    fn from(value: ApiKeyHashing) -> Self {
+        Self {
+            algorithm: value.algorithm,
+            version: value.version as u32,
+            m_cost: value.m_cost as u32,
+            t_cost: value.t_cost as u32,
+            p_cost: value.p_cost as u32,
+        }
+    }
+}
+
+impl From<&HashParams> for ApiKeyHashing {
+    fn from(value: &HashParams) -> Self {
+        Self {
+            algorithm: value.algorithm.clone(),
+            version: value.version as i64,
+            m_cost: value.m_cost as i64,
+            t_cost: value.t_cost as i64,
+            p_cost: value.p_cost as i64,
+        }
+    }
+}
+
+/// Operations that an api key can be granted access to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    ResourcesRead,
+    ResourcesWrite,
+    BillingRead,
+    ApiKeysWrite,
+    MembersWrite,
+    AuditRead,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [
+        Scope::ResourcesRead,
+        Scope::ResourcesWrite,
+        Scope::BillingRead,
+        Scope::ApiKeysWrite,
+        Scope::MembersWrite,
+        Scope::AuditRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ResourcesRead => "resources:read",
+            Scope::ResourcesWrite => "resources:write",
+            Scope::BillingRead => "billing:read",
+            Scope::ApiKeysWrite => "apikeys:write",
+            Scope::MembersWrite => "members:write",
+            Scope::AuditRead => "audit:read",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Scope::ALL
+            .into_iter()
+            .find(|x| x.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown scope {s}"))
+    }
+}
+
+pub fn format_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(Scope::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub fn parse_scopes(scopes: &str) -> Result<Vec<Scope>> {
+    scopes
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(Scope::from_str)
+        .collect()
+}
+
+pub fn random_salt() -> HashSalt {
+    rand::random::<[u8; SALT_LENGTH]>().to_vec()
+}
+
+pub fn random_key_id() -> ApiKeyId {
+    rand::random::<[u8; KEY_ID_LENGTH]>().to_vec()
+}
+
+/// Compares two digests without short-circuiting on the first mismatch
+pub fn digest_matches(expected: &[u8], actual: &[u8]) -> bool {
+    expected.ct_eq(actual).into()
+}
+
+pub fn digest(pwd: &[u8], salt: &[u8], params: &HashParams) -> Result<HashDigest> {
+    let algorithm = Algorithm::new(&params.algorithm)
+        .map_err(|err| anyhow!(err.to_string()))
+        .context("invalid hash algorithm")?;
+
+    let version = Version::try_from(params.version)
+        .map_err(|err| anyhow!(err.to_string()))
+        .context("invalid hash version")?;
+
+    let costs = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|err| anyhow!(err.to_string()))
+        .context("invalid hash params")?;
+
+    let argon2 = Argon2::new(algorithm, version, costs);
 
     let mut digest = [0u8; 32];
     argon2
@@ -14,3 +163,196 @@ pub fn digest(pwd: &[u8], salt: &[u8]) -> Result<HashDigest> {
 
     Ok(digest)
 }
+
+/// Computes a digest that uniquely identifies a command and its arguments
+///
+/// The digest is the SHA-256 of the operation name followed by each field, all
+/// of them prefixed with their length as a big endian u64. The prefix keeps
+/// different splits of the same bytes from producing the same digest.
+pub fn command_digest(op: &str, fields: &[&[u8]]) -> HashDigest {
+    let mut hasher = Sha256::new();
+
+    for field in std::iter::once(op.as_bytes()).chain(fields.iter().copied()) {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field);
+    }
+
+    hasher.finalize().into()
+}
+
+/// Builds the payload that owners and members are expected to sign
+///
+/// The layout is `namespace || 0x00 || command digest || timestamp (u64, big
+/// endian)`, the fields each operation digests are listed in the README.
+pub fn signature_payload(ns: &str, cmd: &HashDigest, timestamp: AuthTimestamp) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(ns.len() + 1 + cmd.len() + 8);
+
+    payload.extend_from_slice(ns.as_bytes());
+    payload.push(0);
+    payload.extend_from_slice(cmd);
+    payload.extend_from_slice(&timestamp.to_be_bytes());
+
+    payload
+}
+
+pub fn unix_now() -> Result<Timestamp> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system clock before unix epoch")?
+        .as_secs();
+
+    Ok(now)
+}
+
+pub fn assert_fresh_timestamp(timestamp: AuthTimestamp) -> Result<()> {
+    let now = unix_now()?;
+
+    if now.abs_diff(timestamp) > MAX_SIGNATURE_SKEW {
+        bail!("signature timestamp out of range")
+    }
+
+    Ok(())
+}
+
+pub fn assert_within_validity(
+    now: Timestamp,
+    not_before: Option<Timestamp>,
+    not_after: Option<Timestamp>,
+) -> Result<()> {
+    if not_before.is_some_and(|x| now < x) {
+        bail!("api key not yet valid")
+    }
+
+    if not_after.is_some_and(|x| now >= x) {
+        bail!("api key expired")
+    }
+
+    Ok(())
+}
+
+fn parse_public_key(public_key: &[u8]) -> Result<VerifyingKey> {
+    let public_key: [u8; 32] = public_key
+        .try_into()
+        .map_err(|_| anyhow!("malformed public key"))?;
+
+    VerifyingKey::from_bytes(&public_key).context("invalid public key")
+}
+
+pub fn assert_valid_public_key(public_key: &[u8]) -> Result<()> {
+    parse_public_key(public_key).map(|_| ())
+}
+
+pub fn verify_signature(
+    public_key: &[u8],
+    signature: &SignatureValue,
+    payload: &[u8],
+) -> Result<()> {
+    let public_key = parse_public_key(public_key)?;
+
+    let signature = hex::decode(signature).context("malformed signature encoding")?;
+    let signature = Signature::from_slice(&signature).context("malformed signature")?;
+
+    public_key
+        .verify_strict(payload, &signature)
+        .map_err(|_| anyhow!("invalid signature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    fn now() -> AuthTimestamp {
+        unix_now().unwrap()
+    }
+
+    #[test]
+    fn digest_depends_on_params() {
+        let salt = random_salt();
+        let default = HashParams::default();
+
+        let stronger = HashParams {
+            t_cost: default.t_cost + 1,
+            ..HashParams::default()
+        };
+
+        let a = digest(b"mybadpassword", &salt, &default).unwrap();
+        let b = digest(b"mybadpassword", &salt, &stronger).unwrap();
+
+        assert_ne!(a, b);
+        assert_eq!(a, digest(b"mybadpassword", &salt, &default).unwrap());
+        assert_ne!(salt, random_salt());
+    }
+
+    #[test]
+    fn signature_roundtrip() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let cmd = command_digest("register_apikey", &[b"ns1"]);
+        let payload = signature_payload("ns1", &cmd, 1234);
+
+        let signature = hex::encode(key.sign(&payload).to_bytes());
+
+        verify_signature(key.verifying_key().as_bytes(), &signature, &payload).unwrap();
+
+        let other = signature_payload("ns2", &cmd, 1234);
+        assert!(verify_signature(key.verifying_key().as_bytes(), &signature, &other).is_err());
+    }
+
+    #[test]
+    fn scopes_roundtrip() {
+        let scopes = vec![Scope::ResourcesRead, Scope::BillingRead];
+        let formatted = format_scopes(&scopes);
+
+        assert_eq!(formatted, "resources:read,billing:read");
+        assert_eq!(parse_scopes(&formatted).unwrap(), scopes);
+        assert!(parse_scopes("resources:delete").is_err());
+    }
+
+    #[test]
+    fn validity_window() {
+        assert!(assert_within_validity(100, None, None).is_ok());
+        assert!(assert_within_validity(100, Some(100), Some(101)).is_ok());
+        assert!(assert_within_validity(99, Some(100), None).is_err());
+        assert!(assert_within_validity(101, None, Some(101)).is_err());
+    }
+
+    #[test]
+    fn signatures_match_the_documented_vector() {
+        let cmd = command_digest(
+            "read_resource",
+            &[b"name", b"workers.demeter.run/v1alpha1", b"res1"],
+        );
+
+        assert_eq!(
+            hex::encode(cmd),
+            "099a944878a8459abff45acabcaf5c8ec6806566e1aabd37ac05b34d95805f09"
+        );
+
+        let payload = signature_payload("ns1", &cmd, 1_700_000_000);
+
+        assert_eq!(
+            hex::encode(&payload),
+            "6e733100099a944878a8459abff45acabcaf5c8ec6806566e1aabd37ac05b34d95805f09000000006553f100"
+        );
+
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let signature = hex::encode(key.sign(&payload).to_bytes());
+
+        assert_eq!(
+            signature,
+            "f2f4777538c991432b11fdfe466f0f3051330a7d4a071a29a7280ab5d1aea4fa\
+             3683934e69f118d75bcd6ef467d6506483ebfd4f64a151df62a8f86ee0044700"
+        );
+
+        let public_key = key.verifying_key().to_bytes();
+        assert!(verify_signature(&public_key, &signature, &payload).is_ok());
+    }
+
+    #[test]
+    fn stale_timestamps_are_rejected() {
+        assert!(assert_fresh_timestamp(now()).is_ok());
+        assert!(assert_fresh_timestamp(now() - MAX_SIGNATURE_SKEW - 10).is_err());
+        assert!(assert_fresh_timestamp(now() + MAX_SIGNATURE_SKEW + 10).is_err());
+    }
+}
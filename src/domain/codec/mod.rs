@@ -0,0 +1,846 @@
+//! Binary encoding of events
+//!
+//! Events are encoded as protobuf messages wrapped in an envelope that carries
+//! the schema version and a type tag naming the event. Tags are the names of
+//! the versioned event structs, which never change once released: evolving an
+//! event means adding a new version of it rather than editing the old one.
+//! Field numbers follow the usual protobuf rules, they're never reused or
+//! renumbered.
+
+use anyhow::{anyhow, bail, Context, Result};
+use prost::Message;
+use std::str::FromStr;
+
+use super::*;
+
+/// Version of the envelope layout. It only changes if the envelope itself has
+/// to change in a way that older readers can't cope with.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown event type {0}")]
+pub struct UnknownEventType(pub String);
+
+mod wire {
+    use std::collections::BTreeMap;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Envelope {
+        #[prost(uint32, tag = "1")]
+        pub schema_version: u32,
+        #[prost(string, tag = "2")]
+        pub r#type: String,
+        #[prost(bytes = "vec", tag = "3")]
+        pub payload: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceMetadataV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(string, tag = "2")]
+        pub kind: String,
+        #[prost(string, tag = "3")]
+        pub name: String,
+        #[prost(bytes = "vec", tag = "4")]
+        pub uuid: Vec<u8>,
+        #[prost(btree_map = "string, string", tag = "5")]
+        pub labels: BTreeMap<String, String>,
+        #[prost(btree_map = "string, string", tag = "6")]
+        pub annotations: BTreeMap<String, String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HashParams {
+        #[prost(string, tag = "1")]
+        pub algorithm: String,
+        #[prost(uint32, tag = "2")]
+        pub version: u32,
+        #[prost(uint32, tag = "3")]
+        pub m_cost: u32,
+        #[prost(uint32, tag = "4")]
+        pub t_cost: u32,
+        #[prost(uint32, tag = "5")]
+        pub p_cost: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct NamespaceMintedV1 {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub root_public_key: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ApiKeyRegisteredV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub key_id: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub digest: Vec<u8>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub salt: Vec<u8>,
+        #[prost(message, optional, tag = "5")]
+        pub hash_params: Option<HashParams>,
+        #[prost(string, repeated, tag = "6")]
+        pub scopes: Vec<String>,
+        #[prost(uint64, optional, tag = "7")]
+        pub not_before: Option<u64>,
+        #[prost(uint64, optional, tag = "8")]
+        pub not_after: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ApiKeyRevokedV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub key_id: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ApiKeyRotatedV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub key_id: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub replaced_by: Vec<u8>,
+        #[prost(uint64, tag = "4")]
+        pub not_after: u64,
+    }
+
+    /// Shared by `MemberAddedV1` and `MemberRoleChangedV1`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MemberRoleV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub public_key: Vec<u8>,
+        #[prost(string, tag = "3")]
+        pub role: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MemberRemovedV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub public_key: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceCreatedV1 {
+        #[prost(message, optional, tag = "1")]
+        pub metadata: Option<ResourceMetadataV1>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub manifest: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourcePatchedV1 {
+        #[prost(message, optional, tag = "1")]
+        pub metadata: Option<ResourceMetadataV1>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub manifest: Vec<u8>,
+        #[prost(uint64, tag = "3")]
+        pub revision: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceCreatedV2 {
+        #[prost(message, optional, tag = "1")]
+        pub metadata: Option<ResourceMetadataV1>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub manifest: Vec<u8>,
+        #[prost(uint64, optional, tag = "3")]
+        pub timestamp: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourcePatchedV2 {
+        #[prost(message, optional, tag = "1")]
+        pub metadata: Option<ResourceMetadataV1>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub manifest: Vec<u8>,
+        #[prost(uint64, tag = "3")]
+        pub revision: u64,
+        #[prost(uint64, optional, tag = "4")]
+        pub timestamp: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceDeletedV1 {
+        #[prost(message, optional, tag = "1")]
+        pub metadata: Option<ResourceMetadataV1>,
+        #[prost(string, repeated, tag = "2")]
+        pub finalizers: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceFinalizedV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub resource: Vec<u8>,
+        #[prost(string, tag = "3")]
+        pub finalizer: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceStatusUpdatedV1 {
+        #[prost(string, tag = "1")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub resource: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub status: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceUsageV1 {
+        #[prost(bytes = "vec", tag = "1")]
+        pub entry: Vec<u8>,
+        #[prost(uint64, tag = "2")]
+        pub epoch: u64,
+        #[prost(string, tag = "3")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "4")]
+        pub resource: Vec<u8>,
+        #[prost(bytes = "vec", tag = "5")]
+        pub cluster: Vec<u8>,
+        #[prost(uint64, tag = "6")]
+        pub units: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UsagePaymentV1 {
+        #[prost(bytes = "vec", tag = "1")]
+        pub entry: Vec<u8>,
+        #[prost(uint64, tag = "2")]
+        pub epoch: u64,
+        #[prost(string, tag = "3")]
+        pub namespace: String,
+        #[prost(bytes = "vec", tag = "4")]
+        pub cluster: Vec<u8>,
+        #[prost(uint64, tag = "5")]
+        pub units: u64,
+    }
+}
+
+fn metadata_to_wire(value: &ResourceMetadataV1) -> wire::ResourceMetadataV1 {
+    wire::ResourceMetadataV1 {
+        namespace: value.namespace.clone(),
+        kind: value.kind.clone(),
+        name: value.name.clone(),
+        uuid: value.uuid.clone(),
+        labels: value.labels.clone(),
+        annotations: value.annotations.clone(),
+    }
+}
+
+fn metadata_from_wire(value: Option<wire::ResourceMetadataV1>) -> Result<ResourceMetadataV1> {
+    let value = value.ok_or_else(|| anyhow!("missing resource metadata"))?;
+
+    Ok(ResourceMetadataV1 {
+        namespace: value.namespace,
+        kind: value.kind,
+        name: value.name,
+        uuid: value.uuid,
+        labels: value.labels,
+        annotations: value.annotations,
+    })
+}
+
+fn encode_payload(event: &Event) -> Vec<u8> {
+    match event {
+        Event::NamespaceMintedV1(x) => wire::NamespaceMintedV1 {
+            name: x.name.clone(),
+            root_public_key: x.root_public_key.clone(),
+        }
+        .encode_to_vec(),
+        Event::ApiKeyRegisteredV1(x) => wire::ApiKeyRegisteredV1 {
+            namespace: x.namespace.clone(),
+            key_id: x.key_id.clone(),
+            digest: x.digest.to_vec(),
+            salt: x.salt.clone(),
+            hash_params: Some(wire::HashParams {
+                algorithm: x.hash_params.algorithm.clone(),
+                version: x.hash_params.version,
+                m_cost: x.hash_params.m_cost,
+                t_cost: x.hash_params.t_cost,
+                p_cost: x.hash_params.p_cost,
+            }),
+            scopes: x.scopes.iter().map(|x| x.as_str().to_owned()).collect(),
+            not_before: x.not_before,
+            not_after: x.not_after,
+        }
+        .encode_to_vec(),
+        Event::ApiKeyRevokedV1(x) => wire::ApiKeyRevokedV1 {
+            namespace: x.namespace.clone(),
+            key_id: x.key_id.clone(),
+        }
+        .encode_to_vec(),
+        Event::ApiKeyRotatedV1(x) => wire::ApiKeyRotatedV1 {
+            namespace: x.namespace.clone(),
+            key_id: x.key_id.clone(),
+            replaced_by: x.replaced_by.clone(),
+            not_after: x.not_after,
+        }
+        .encode_to_vec(),
+        Event::MemberAddedV1(x) => wire::MemberRoleV1 {
+            namespace: x.namespace.clone(),
+            public_key: x.public_key.clone(),
+            role: x.role.as_str().to_owned(),
+        }
+        .encode_to_vec(),
+        Event::MemberRoleChangedV1(x) => wire::MemberRoleV1 {
+            namespace: x.namespace.clone(),
+            public_key: x.public_key.clone(),
+            role: x.role.as_str().to_owned(),
+        }
+        .encode_to_vec(),
+        Event::MemberRemovedV1(x) => wire::MemberRemovedV1 {
+            namespace: x.namespace.clone(),
+            public_key: x.public_key.clone(),
+        }
+        .encode_to_vec(),
+        Event::ResourceCreatedV2(x) => wire::ResourceCreatedV2 {
+            metadata: Some(metadata_to_wire(&x.metadata)),
+            manifest: x.manifest.clone(),
+            timestamp: x.timestamp,
+        }
+        .encode_to_vec(),
+        Event::ResourcePatchedV2(x) => wire::ResourcePatchedV2 {
+            metadata: Some(metadata_to_wire(&x.metadata)),
+            manifest: x.manifest.clone(),
+            revision: x.revision,
+            timestamp: x.timestamp,
+        }
+        .encode_to_vec(),
+        Event::ResourceStatusUpdatedV1(x) => wire::ResourceStatusUpdatedV1 {
+            namespace: x.namespace.clone(),
+            resource: x.resource.clone(),
+            status: x.status.clone(),
+        }
+        .encode_to_vec(),
+        Event::ResourceDeletedV1(x) => wire::ResourceDeletedV1 {
+            metadata: Some(metadata_to_wire(&x.metadata)),
+            finalizers: x.finalizers.clone(),
+        }
+        .encode_to_vec(),
+        Event::ResourceFinalizedV1(x) => wire::ResourceFinalizedV1 {
+            namespace: x.namespace.clone(),
+            resource: x.resource.clone(),
+            finalizer: x.finalizer.clone(),
+        }
+        .encode_to_vec(),
+        Event::ResourceUsageV1(x) => wire::ResourceUsageV1 {
+            entry: x.entry.clone(),
+            epoch: x.epoch,
+            namespace: x.namespace.clone(),
+            resource: x.resource.clone(),
+            cluster: x.cluster.clone(),
+            units: x.units,
+        }
+        .encode_to_vec(),
+        Event::UsagePaymentV1(x) => wire::UsagePaymentV1 {
+            entry: x.entry.clone(),
+            epoch: x.epoch,
+            namespace: x.namespace.clone(),
+            cluster: x.cluster.clone(),
+            units: x.units,
+        }
+        .encode_to_vec(),
+    }
+}
+
+fn decode_payload(tag: &str, payload: &[u8]) -> Result<VersionedEvent> {
+    let event: Event = match tag {
+        "NamespaceMintedV1" => {
+            let x = wire::NamespaceMintedV1::decode(payload)?;
+
+            NamespaceMintedV1 {
+                name: x.name,
+                root_public_key: x.root_public_key,
+            }
+            .into()
+        }
+        "ApiKeyRegisteredV1" => {
+            let x = wire::ApiKeyRegisteredV1::decode(payload)?;
+            let hash_params = x
+                .hash_params
+                .ok_or_else(|| anyhow!("missing hash params"))?;
+
+            ApiKeyRegisteredV1 {
+                namespace: x.namespace,
+                key_id: x.key_id,
+                digest: x
+                    .digest
+                    .try_into()
+                    .map_err(|_| anyhow!("invalid digest length"))?,
+                salt: x.salt,
+                hash_params: HashParams {
+                    algorithm: hash_params.algorithm,
+                    version: hash_params.version,
+                    m_cost: hash_params.m_cost,
+                    t_cost: hash_params.t_cost,
+                    p_cost: hash_params.p_cost,
+                },
+                scopes: x
+                    .scopes
+                    .iter()
+                    .map(|x| Scope::from_str(x))
+                    .collect::<Result<_>>()?,
+                not_before: x.not_before,
+                not_after: x.not_after,
+            }
+            .into()
+        }
+        "ApiKeyRevokedV1" => {
+            let x = wire::ApiKeyRevokedV1::decode(payload)?;
+
+            ApiKeyRevokedV1 {
+                namespace: x.namespace,
+                key_id: x.key_id,
+            }
+            .into()
+        }
+        "ApiKeyRotatedV1" => {
+            let x = wire::ApiKeyRotatedV1::decode(payload)?;
+
+            ApiKeyRotatedV1 {
+                namespace: x.namespace,
+                key_id: x.key_id,
+                replaced_by: x.replaced_by,
+                not_after: x.not_after,
+            }
+            .into()
+        }
+        "MemberAddedV1" => {
+            let x = wire::MemberRoleV1::decode(payload)?;
+
+            MemberAddedV1 {
+                namespace: x.namespace,
+                public_key: x.public_key,
+                role: Role::from_str(&x.role)?,
+            }
+            .into()
+        }
+        "MemberRoleChangedV1" => {
+            let x = wire::MemberRoleV1::decode(payload)?;
+
+            MemberRoleChangedV1 {
+                namespace: x.namespace,
+                public_key: x.public_key,
+                role: Role::from_str(&x.role)?,
+            }
+            .into()
+        }
+        "MemberRemovedV1" => {
+            let x = wire::MemberRemovedV1::decode(payload)?;
+
+            MemberRemovedV1 {
+                namespace: x.namespace,
+                public_key: x.public_key,
+            }
+            .into()
+        }
+        "ResourceCreatedV1" => {
+            let x = wire::ResourceCreatedV1::decode(payload)?;
+
+            let legacy = LegacyEvent::ResourceCreatedV1(ResourceCreatedV1 {
+                metadata: metadata_from_wire(x.metadata)?,
+                manifest: x.manifest,
+            });
+
+            return Ok(VersionedEvent::Legacy(legacy));
+        }
+        "ResourceCreatedV2" => {
+            let x = wire::ResourceCreatedV2::decode(payload)?;
+
+            ResourceCreatedV2 {
+                metadata: metadata_from_wire(x.metadata)?,
+                manifest: x.manifest,
+                timestamp: x.timestamp,
+            }
+            .into()
+        }
+        "ResourcePatchedV1" => {
+            let x = wire::ResourcePatchedV1::decode(payload)?;
+
+            let legacy = LegacyEvent::ResourcePatchedV1(ResourcePatchedV1 {
+                metadata: metadata_from_wire(x.metadata)?,
+                manifest: x.manifest,
+                revision: x.revision,
+            });
+
+            return Ok(VersionedEvent::Legacy(legacy));
+        }
+        "ResourcePatchedV2" => {
+            let x = wire::ResourcePatchedV2::decode(payload)?;
+
+            ResourcePatchedV2 {
+                metadata: metadata_from_wire(x.metadata)?,
+                manifest: x.manifest,
+                revision: x.revision,
+                timestamp: x.timestamp,
+            }
+            .into()
+        }
+        "ResourceStatusUpdatedV1" => {
+            let x = wire::ResourceStatusUpdatedV1::decode(payload)?;
+
+            ResourceStatusUpdatedV1 {
+                namespace: x.namespace,
+                resource: x.resource,
+                status: x.status,
+            }
+            .into()
+        }
+        "ResourceDeletedV1" => {
+            let x = wire::ResourceDeletedV1::decode(payload)?;
+
+            ResourceDeletedV1 {
+                metadata: metadata_from_wire(x.metadata)?,
+                finalizers: x.finalizers,
+            }
+            .into()
+        }
+        "ResourceFinalizedV1" => {
+            let x = wire::ResourceFinalizedV1::decode(payload)?;
+
+            ResourceFinalizedV1 {
+                namespace: x.namespace,
+                resource: x.resource,
+                finalizer: x.finalizer,
+            }
+            .into()
+        }
+        "ResourceUsageV1" => {
+            let x = wire::ResourceUsageV1::decode(payload)?;
+
+            ResourceUsageV1 {
+                entry: x.entry,
+                epoch: x.epoch,
+                namespace: x.namespace,
+                resource: x.resource,
+                cluster: x.cluster,
+                units: x.units,
+            }
+            .into()
+        }
+        "UsagePaymentV1" => {
+            let x = wire::UsagePaymentV1::decode(payload)?;
+
+            UsagePaymentV1 {
+                entry: x.entry,
+                epoch: x.epoch,
+                namespace: x.namespace,
+                cluster: x.cluster,
+                units: x.units,
+            }
+            .into()
+        }
+        x => return Err(UnknownEventType(x.to_owned()).into()),
+    };
+
+    Ok(VersionedEvent::Current(event))
+}
+
+impl Event {
+    /// Name of the event variant, used as its type tag on the wire
+    pub fn type_tag(&self) -> &'static str {
+        match self {
+            Event::NamespaceMintedV1(_) => "NamespaceMintedV1",
+            Event::ApiKeyRegisteredV1(_) => "ApiKeyRegisteredV1",
+            Event::ApiKeyRevokedV1(_) => "ApiKeyRevokedV1",
+            Event::ApiKeyRotatedV1(_) => "ApiKeyRotatedV1",
+            Event::MemberAddedV1(_) => "MemberAddedV1",
+            Event::MemberRoleChangedV1(_) => "MemberRoleChangedV1",
+            Event::MemberRemovedV1(_) => "MemberRemovedV1",
+            Event::ResourceCreatedV2(_) => "ResourceCreatedV2",
+            Event::ResourcePatchedV2(_) => "ResourcePatchedV2",
+            Event::ResourceStatusUpdatedV1(_) => "ResourceStatusUpdatedV1",
+            Event::ResourceDeletedV1(_) => "ResourceDeletedV1",
+            Event::ResourceFinalizedV1(_) => "ResourceFinalizedV1",
+            Event::ResourceUsageV1(_) => "ResourceUsageV1",
+            Event::UsagePaymentV1(_) => "UsagePaymentV1",
+        }
+    }
+}
+
+pub fn encode_event(event: &Event) -> Vec<u8> {
+    wire::Envelope {
+        schema_version: EVENT_SCHEMA_VERSION,
+        r#type: event.type_tag().to_owned(),
+        payload: encode_payload(event),
+    }
+    .encode_to_vec()
+}
+
+/// Decodes an event in the version it was encoded with
+pub fn decode_versioned_event(bytes: &[u8]) -> Result<VersionedEvent> {
+    let envelope = wire::Envelope::decode(bytes).context("decoding event envelope")?;
+
+    if envelope.schema_version != EVENT_SCHEMA_VERSION {
+        bail!(
+            "unsupported event schema version {}",
+            envelope.schema_version
+        )
+    }
+
+    decode_payload(&envelope.r#type, &envelope.payload)
+        .with_context(|| format!("decoding {} event", envelope.r#type))
+}
+
+/// Decodes an event and upcasts it to its latest version
+pub fn decode_event(bytes: &[u8]) -> Result<Event> {
+    upcast(decode_versioned_event(bytes)?)
+}
+
+/// Decodes an event from the json written by early event logs and upcasts it
+/// to its latest version
+pub fn decode_json_event(bytes: &[u8]) -> Result<Event> {
+    let value: serde_json::Value = serde_json::from_slice(bytes)?;
+
+    // variants are externally tagged, the only key is the type tag
+    let legacy = value
+        .as_object()
+        .and_then(|x| x.keys().next())
+        .is_some_and(|x| is_legacy_tag(x));
+
+    let event = match legacy {
+        true => VersionedEvent::Legacy(serde_json::from_value(value)?),
+        false => VersionedEvent::Current(serde_json::from_value(value)?),
+    };
+
+    upcast(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn metadata() -> ResourceMetadataV1 {
+        ResourceMetadataV1 {
+            namespace: "ns1".into(),
+            kind: "workers.demeter.run/v1alpha1".into(),
+            name: "worker-1".into(),
+            uuid: b"uuid-1".to_vec(),
+            labels: [("env".to_owned(), "prod".to_owned())].into(),
+            annotations: [("note".to_owned(), "first".to_owned())].into(),
+        }
+    }
+
+    /// One sample of every event, the golden files are their encodings
+    fn samples() -> Vec<Event> {
+        vec![
+            NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: vec![1; 32],
+            }
+            .into(),
+            ApiKeyRegisteredV1 {
+                namespace: "ns1".into(),
+                key_id: b"key00001".to_vec(),
+                digest: [2; 32],
+                salt: vec![3; 16],
+                hash_params: HashParams {
+                    algorithm: "argon2id".into(),
+                    version: 19,
+                    m_cost: 19456,
+                    t_cost: 2,
+                    p_cost: 1,
+                },
+                scopes: vec![Scope::ResourcesRead, Scope::AuditRead],
+                not_before: Some(1_700_000_000),
+                not_after: None,
+            }
+            .into(),
+            ApiKeyRevokedV1 {
+                namespace: "ns1".into(),
+                key_id: b"key00001".to_vec(),
+            }
+            .into(),
+            ApiKeyRotatedV1 {
+                namespace: "ns1".into(),
+                key_id: b"key00001".to_vec(),
+                replaced_by: b"key00002".to_vec(),
+                not_after: 1_700_086_400,
+            }
+            .into(),
+            MemberAddedV1 {
+                namespace: "ns1".into(),
+                public_key: vec![4; 32],
+                role: Role::Developer,
+            }
+            .into(),
+            MemberRoleChangedV1 {
+                namespace: "ns1".into(),
+                public_key: vec![4; 32],
+                role: Role::Admin,
+            }
+            .into(),
+            MemberRemovedV1 {
+                namespace: "ns1".into(),
+                public_key: vec![4; 32],
+            }
+            .into(),
+            ResourceCreatedV2 {
+                metadata: metadata(),
+                manifest: br#"{"image":"a"}"#.to_vec(),
+                timestamp: Some(1_700_000_000),
+            }
+            .into(),
+            ResourcePatchedV2 {
+                metadata: metadata(),
+                manifest: br#"{"image":"b"}"#.to_vec(),
+                revision: 2,
+                timestamp: Some(1_700_000_100),
+            }
+            .into(),
+            ResourceStatusUpdatedV1 {
+                namespace: "ns1".into(),
+                resource: b"uuid-1".to_vec(),
+                status: b"running".to_vec(),
+            }
+            .into(),
+            ResourceDeletedV1 {
+                metadata: metadata(),
+                finalizers: vec!["k8s.demeter.run/teardown".into()],
+            }
+            .into(),
+            ResourceFinalizedV1 {
+                namespace: "ns1".into(),
+                resource: b"uuid-1".to_vec(),
+                finalizer: "k8s.demeter.run/teardown".into(),
+            }
+            .into(),
+            ResourceUsageV1 {
+                entry: b"entry-1".to_vec(),
+                epoch: 42,
+                namespace: "ns1".into(),
+                resource: b"uuid-1".to_vec(),
+                cluster: b"cluster-1".to_vec(),
+                units: 400,
+            }
+            .into(),
+            UsagePaymentV1 {
+                entry: b"entry-2".to_vec(),
+                epoch: 42,
+                namespace: "ns1".into(),
+                cluster: b"cluster-1".to_vec(),
+                units: 400,
+            }
+            .into(),
+        ]
+    }
+
+    fn golden_path(type_tag: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/domain/codec/golden")
+            .join(format!("{type_tag}.bin"))
+    }
+
+    /// Set `UPDATE_GOLDEN=1` to write the files for newly added events.
+    /// Existing files must never be regenerated, they stand for events
+    /// already written by released versions.
+    #[test]
+    fn golden_files_roundtrip() {
+        for event in samples() {
+            let path = golden_path(event.type_tag());
+
+            if std::env::var("UPDATE_GOLDEN").is_ok() && !path.exists() {
+                std::fs::write(&path, encode_event(&event)).unwrap();
+            }
+
+            let golden = std::fs::read(&path)
+                .unwrap_or_else(|_| panic!("missing golden file {}", path.display()));
+
+            assert_eq!(decode_event(&golden).unwrap(), event, "{}", path.display());
+            assert_eq!(encode_event(&event), golden, "{}", path.display());
+        }
+    }
+
+    /// Golden files of superseded versions are kept to check that they're
+    /// still read, as their latest version
+    #[test]
+    fn legacy_golden_files_are_upcast() {
+        let expected: Vec<Event> = vec![
+            ResourceCreatedV2 {
+                metadata: metadata(),
+                manifest: br#"{"image":"a"}"#.to_vec(),
+                timestamp: None,
+            }
+            .into(),
+            ResourcePatchedV2 {
+                metadata: metadata(),
+                manifest: br#"{"image":"b"}"#.to_vec(),
+                revision: 2,
+                timestamp: None,
+            }
+            .into(),
+        ];
+
+        for (tag, expected) in ["ResourceCreatedV1", "ResourcePatchedV1"]
+            .into_iter()
+            .zip(expected)
+        {
+            let golden = std::fs::read(golden_path(tag)).unwrap();
+
+            let versioned = decode_versioned_event(&golden).unwrap();
+            assert!(matches!(versioned, VersionedEvent::Legacy(_)), "{tag}");
+
+            assert_eq!(decode_event(&golden).unwrap(), expected, "{tag}");
+        }
+    }
+
+    #[test]
+    fn json_events_are_upcast() {
+        let json = br#"{"ResourcePatchedV1":{"metadata":{"namespace":"ns1","kind":"k","name":"n","uuid":[1],"labels":{},"annotations":{}},"manifest":[123,125],"revision":2}}"#;
+
+        match decode_json_event(json).unwrap() {
+            Event::ResourcePatchedV2(x) => {
+                assert_eq!(x.revision, 2);
+                assert_eq!(x.timestamp, None);
+            }
+            x => panic!("unexpected event {x:?}"),
+        }
+
+        let event: Event = NamespaceMintedV1 {
+            name: "ns1".into(),
+            root_public_key: vec![],
+        }
+        .into();
+
+        let json = serde_json::to_vec(&event).unwrap();
+        assert_eq!(decode_json_event(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn unknown_events_are_rejected() {
+        let bytes = wire::Envelope {
+            schema_version: EVENT_SCHEMA_VERSION,
+            r#type: "NamespaceBurnedV9".into(),
+            payload: vec![],
+        }
+        .encode_to_vec();
+
+        let err = decode_event(&bytes).unwrap_err();
+        assert!(err.is::<UnknownEventType>());
+
+        let bytes = wire::Envelope {
+            schema_version: EVENT_SCHEMA_VERSION + 1,
+            r#type: "NamespaceMintedV1".into(),
+            payload: vec![],
+        }
+        .encode_to_vec();
+
+        assert!(decode_event(&bytes).is_err());
+    }
+}
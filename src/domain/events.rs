@@ -1,4 +1,7 @@
-use super::{HashDigest, HashSalt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::{ApiKeyId, HashDigest, HashParams, HashSalt, MemberKey, Role, Scope, Timestamp};
 
 macro_rules! into_event {
     ($name:ident) => {
@@ -15,18 +18,24 @@ pub type DCU = u64;
 pub type Epoch = u64;
 pub type ResourceUuid = Blob;
 pub type ClusterUuid = Blob;
+pub type Revision = u64;
+pub type Labels = BTreeMap<String, String>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ResourceMetadataV1 {
     pub namespace: NamespaceName,
     pub kind: String,
     pub name: String,
     pub uuid: ResourceUuid,
+    /// Identifying key/value pairs that resources can be selected by
+    pub labels: Labels,
+    /// Non-identifying key/value pairs for tools and users
+    pub annotations: Labels,
 }
 
 pub type Blob = Vec<u8>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NamespaceMintedV1 {
     pub name: String,
     pub root_public_key: Blob,
@@ -34,24 +43,133 @@ pub struct NamespaceMintedV1 {
 
 into_event!(NamespaceMintedV1);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ApiKeyRegisteredV1 {
     pub namespace: String,
+    pub key_id: ApiKeyId,
     pub digest: HashDigest,
     pub salt: HashSalt,
+    pub hash_params: HashParams,
+    pub scopes: Vec<Scope>,
+    pub not_before: Option<Timestamp>,
+    pub not_after: Option<Timestamp>,
 }
 
 into_event!(ApiKeyRegisteredV1);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKeyRevokedV1 {
+    pub namespace: String,
+    pub key_id: ApiKeyId,
+}
+
+into_event!(ApiKeyRevokedV1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKeyRotatedV1 {
+    pub namespace: String,
+    pub key_id: ApiKeyId,
+    pub replaced_by: ApiKeyId,
+    pub not_after: Timestamp,
+}
+
+into_event!(ApiKeyRotatedV1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberAddedV1 {
+    pub namespace: NamespaceName,
+    pub public_key: MemberKey,
+    pub role: Role,
+}
+
+into_event!(MemberAddedV1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberRoleChangedV1 {
+    pub namespace: NamespaceName,
+    pub public_key: MemberKey,
+    pub role: Role,
+}
+
+into_event!(MemberRoleChangedV1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberRemovedV1 {
+    pub namespace: NamespaceName,
+    pub public_key: MemberKey,
+}
+
+into_event!(MemberRemovedV1);
+
+/// Superseded by `ResourceCreatedV2`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ResourceCreatedV1 {
     pub metadata: ResourceMetadataV1,
     pub manifest: Vec<u8>,
 }
 
-into_event!(ResourceCreatedV1);
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceCreatedV2 {
+    pub metadata: ResourceMetadataV1,
+    pub manifest: Vec<u8>,
+    /// When the creation was accepted, `None` for events upcast from V1
+    pub timestamp: Option<Timestamp>,
+}
+
+into_event!(ResourceCreatedV2);
+
+/// Superseded by `ResourcePatchedV2`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourcePatchedV1 {
+    pub metadata: ResourceMetadataV1,
+    /// Full manifest after applying the patch
+    pub manifest: Vec<u8>,
+    pub revision: Revision,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourcePatchedV2 {
+    pub metadata: ResourceMetadataV1,
+    /// Full manifest after applying the patch
+    pub manifest: Vec<u8>,
+    pub revision: Revision,
+    /// When the patch was accepted, `None` for events upcast from V1
+    pub timestamp: Option<Timestamp>,
+}
+
+into_event!(ResourcePatchedV2);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceDeletedV1 {
+    pub metadata: ResourceMetadataV1,
+    /// Finalizers that have to clear before the resource is removed
+    pub finalizers: Vec<String>,
+}
+
+into_event!(ResourceDeletedV1);
+
+/// Reported once the party behind a finalizer is done tearing down a
+/// deleting resource
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceFinalizedV1 {
+    pub namespace: NamespaceName,
+    pub resource: ResourceUuid,
+    pub finalizer: String,
+}
+
+into_event!(ResourceFinalizedV1);
+
+/// Latest status of a resource as reported by the cluster running it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceStatusUpdatedV1 {
+    pub namespace: NamespaceName,
+    pub resource: ResourceUuid,
+    pub status: Blob,
+}
+
+into_event!(ResourceStatusUpdatedV1);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ResourceUsageV1 {
     pub entry: Blob,
     pub epoch: Epoch,
@@ -63,7 +181,7 @@ pub struct ResourceUsageV1 {
 
 into_event!(ResourceUsageV1);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UsagePaymentV1 {
     pub entry: Blob,
     pub epoch: Epoch,
@@ -74,11 +192,22 @@ pub struct UsagePaymentV1 {
 
 into_event!(UsagePaymentV1);
 
-#[derive(Debug, Clone)]
+/// Latest version of every event. Superseded versions are upcast to these
+/// when read from storage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Event {
     NamespaceMintedV1(NamespaceMintedV1),
     ApiKeyRegisteredV1(ApiKeyRegisteredV1),
-    ResourceCreatedV1(ResourceCreatedV1),
+    ApiKeyRevokedV1(ApiKeyRevokedV1),
+    ApiKeyRotatedV1(ApiKeyRotatedV1),
+    MemberAddedV1(MemberAddedV1),
+    MemberRoleChangedV1(MemberRoleChangedV1),
+    MemberRemovedV1(MemberRemovedV1),
+    ResourceCreatedV2(ResourceCreatedV2),
+    ResourcePatchedV2(ResourcePatchedV2),
+    ResourceStatusUpdatedV1(ResourceStatusUpdatedV1),
+    ResourceDeletedV1(ResourceDeletedV1),
+    ResourceFinalizedV1(ResourceFinalizedV1),
     ResourceUsageV1(ResourceUsageV1),
     UsagePaymentV1(UsagePaymentV1),
 }
@@ -0,0 +1,289 @@
+use anyhow::{anyhow, bail, Result};
+use std::str::FromStr;
+
+use super::Labels;
+
+const MAX_NAME_LEN: usize = 63;
+const MAX_PREFIX_LEN: usize = 253;
+
+fn is_name(value: &str) -> bool {
+    let alphanumeric = |x: Option<char>| x.is_some_and(|x| x.is_ascii_alphanumeric());
+
+    value.len() <= MAX_NAME_LEN
+        && alphanumeric(value.chars().next())
+        && alphanumeric(value.chars().last())
+        && value
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '-' | '_' | '.'))
+}
+
+fn is_prefix(value: &str) -> bool {
+    value.len() <= MAX_PREFIX_LEN
+        && value.split('.').all(|x| {
+            is_name(x)
+                && x.chars()
+                    .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '-')
+        })
+}
+
+/// Checks a label or annotation key, an optional dns prefix followed by a name
+/// such as `demeter.run/tier`
+fn check_key(key: &str) -> Result<()> {
+    let valid = match key.split_once('/') {
+        Some((prefix, name)) => is_prefix(prefix) && is_name(name),
+        None => is_name(key),
+    };
+
+    if !valid {
+        bail!("invalid key {key:?}")
+    }
+
+    Ok(())
+}
+
+fn check_label_value(value: &str) -> Result<()> {
+    if !value.is_empty() && !is_name(value) {
+        bail!("invalid label value {value:?}")
+    }
+
+    Ok(())
+}
+
+/// Checks that labels and annotations are well-formed. Annotation values are
+/// free-form.
+pub fn validate_labels(labels: &Labels, annotations: &Labels) -> Result<()> {
+    for (key, value) in labels {
+        check_key(key)?;
+        check_label_value(value)?;
+    }
+
+    for key in annotations.keys() {
+        check_key(key)?;
+    }
+
+    Ok(())
+}
+
+/// Encodes labels as length-prefixed keys and values, to be used as a command
+/// digest field
+pub(crate) fn digest_labels(labels: &Labels) -> Vec<u8> {
+    let mut out = vec![];
+
+    for (key, value) in labels {
+        for field in [key, value] {
+            out.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelOperator {
+    In,
+    NotIn,
+    Exists,
+    DoesNotExist,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelRequirement {
+    pub key: String,
+    pub operator: LabelOperator,
+    /// Values for `In` and `NotIn`, empty otherwise
+    pub values: Vec<String>,
+}
+
+/// Kubernetes-style label selector, such as `env=prod,tier!=db,zone in (a,b)`.
+/// A resource matches when it satisfies every requirement.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector(pub Vec<LabelRequirement>);
+
+impl LabelSelector {
+    pub fn requirements(&self) -> &[LabelRequirement] {
+        &self.0
+    }
+
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.0.iter().all(|req| {
+            let value = labels.get(&req.key);
+
+            match req.operator {
+                LabelOperator::In => value.is_some_and(|x| req.values.contains(x)),
+                LabelOperator::NotIn => !value.is_some_and(|x| req.values.contains(x)),
+                LabelOperator::Exists => value.is_some(),
+                LabelOperator::DoesNotExist => value.is_none(),
+            }
+        })
+    }
+}
+
+/// Splits a selector on the commas that aren't part of a set of values
+fn split_terms(selector: &str) -> Result<Vec<&str>> {
+    let mut terms = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, x) in selector.char_indices() {
+        match x {
+            '(' => depth += 1,
+            ')' if depth == 0 => bail!("unbalanced parenthesis in selector"),
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                terms.push(&selector[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    if depth != 0 {
+        bail!("unbalanced parenthesis in selector")
+    }
+
+    terms.push(&selector[start..]);
+
+    Ok(terms)
+}
+
+fn requirement(
+    key: &str,
+    operator: LabelOperator,
+    values: Vec<String>,
+) -> Result<LabelRequirement> {
+    let key = key.trim();
+    check_key(key)?;
+
+    for value in values.iter() {
+        check_label_value(value)?;
+    }
+
+    Ok(LabelRequirement {
+        key: key.to_owned(),
+        operator,
+        values,
+    })
+}
+
+fn parse_set(key: &str, operator: &str, values: &str) -> Result<LabelRequirement> {
+    let operator = match operator {
+        "in" => LabelOperator::In,
+        "notin" => LabelOperator::NotIn,
+        x => bail!("unknown selector operator {x}"),
+    };
+
+    let values: Vec<_> = values.split(',').map(|x| x.trim().to_owned()).collect();
+
+    requirement(key, operator, values)
+}
+
+fn parse_term(term: &str) -> Result<LabelRequirement> {
+    if let Some((left, values)) = term.split_once('(') {
+        let values = values
+            .strip_suffix(')')
+            .ok_or_else(|| anyhow!("invalid selector term {term:?}"))?;
+
+        let mut left = left.split_whitespace();
+
+        return match (left.next(), left.next(), left.next()) {
+            (Some(key), Some(operator), None) => parse_set(key, operator, values),
+            _ => bail!("invalid selector term {term:?}"),
+        };
+    }
+
+    if let Some((key, value)) = term.split_once("!=") {
+        return requirement(key, LabelOperator::NotIn, vec![value.trim().to_owned()]);
+    }
+
+    if let Some((key, value)) = term.split_once("==").or_else(|| term.split_once('=')) {
+        return requirement(key, LabelOperator::In, vec![value.trim().to_owned()]);
+    }
+
+    match term.strip_prefix('!') {
+        Some(key) => requirement(key, LabelOperator::DoesNotExist, vec![]),
+        None => requirement(term, LabelOperator::Exists, vec![]),
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let requirements = split_terms(s)?
+            .into_iter()
+            .map(|x| parse_term(x.trim()))
+            .collect::<Result<_>>()?;
+
+        Ok(Self(requirements))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn selectors_are_parsed() {
+        let selector: LabelSelector = "env=prod, tier!=db,zone in (a, b),!legacy,demeter.run/owner"
+            .parse()
+            .unwrap();
+
+        let ops: Vec<_> = selector.requirements().iter().map(|x| x.operator).collect();
+        assert_eq!(
+            ops,
+            vec![
+                LabelOperator::In,
+                LabelOperator::NotIn,
+                LabelOperator::In,
+                LabelOperator::DoesNotExist,
+                LabelOperator::Exists,
+            ]
+        );
+
+        assert_eq!(selector.requirements()[2].values, vec!["a", "b"]);
+        assert_eq!(selector.requirements()[4].key, "demeter.run/owner");
+
+        assert!(""
+            .parse::<LabelSelector>()
+            .unwrap()
+            .requirements()
+            .is_empty());
+        assert!("env in (a".parse::<LabelSelector>().is_err());
+        assert!("env within (a)".parse::<LabelSelector>().is_err());
+        assert!("-env=prod".parse::<LabelSelector>().is_err());
+    }
+
+    #[test]
+    fn selectors_match_labels() {
+        let selector: LabelSelector = "env=prod,tier notin (db),!legacy".parse().unwrap();
+
+        assert!(selector.matches(&labels(&[("env", "prod")])));
+        assert!(selector.matches(&labels(&[("env", "prod"), ("tier", "web")])));
+        assert!(!selector.matches(&labels(&[("env", "prod"), ("tier", "db")])));
+        assert!(!selector.matches(&labels(&[("env", "prod"), ("legacy", "")])));
+        assert!(!selector.matches(&labels(&[("env", "dev")])));
+    }
+
+    #[test]
+    fn labels_are_validated() {
+        assert!(validate_labels(&labels(&[("demeter.run/tier", "web-1")]), &labels(&[])).is_ok());
+        assert!(validate_labels(&labels(&[("tier", "")]), &labels(&[])).is_ok());
+        assert!(validate_labels(&labels(&[("Demeter.run/tier", "web")]), &labels(&[])).is_err());
+        assert!(validate_labels(&labels(&[("tier", "not valid")]), &labels(&[])).is_err());
+
+        let annotations = labels(&[("note", "free form, text!")]);
+        assert!(validate_labels(&labels(&[]), &annotations).is_ok());
+    }
+}
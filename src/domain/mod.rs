@@ -4,44 +4,164 @@
 /// - handle extrinsic events to update the internal state
 /// - handle extrinsic events to actuate on outside systems
 /// - execute commands and emit intrinsic events
-use anyhow::{bail, Result};
-use tracing::info;
-
-use crate::driven::event_dispatch::EventDispatch;
-use crate::driven::fabric_state::{AccountDelta, FabricState};
-
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sqlx::{Sqlite, Transaction};
+use std::collections::{HashMap, HashSet, VecDeque};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+use crate::driven::event_dispatch::{
+    EventDispatch, EventReceipt, EventSequence, EventWrapper, ResumeFrom, ResyncRequired,
+};
+use crate::driven::fabric_state::{
+    AccountDelta, ApiKey, ApiKeyValidity, AuditRecord, FabricState, ResourceCursor, ResourceFilter,
+    ResourceRevision, ResourceRow,
+};
+
+mod access;
+mod audit;
 mod auth;
+mod codec;
 mod events;
+mod labels;
+mod patch;
+mod schema;
+mod upcast;
 
+pub use access::*;
 pub use auth::*;
+pub use codec::*;
 pub use events::*;
+pub use labels::*;
+pub use patch::*;
+pub use schema::*;
+pub use upcast::*;
+
+use audit::AuditTrail;
 
 pub struct Config {
     pub cluster: ClusterUuid,
+    pub apikey_hashing: HashParams,
+    /// Seconds that a rotated api key remains valid after its replacement is
+    /// issued
+    pub apikey_rotation_grace: u64,
+    /// Finalizers registered on every resource being deleted, the resource is
+    /// removed once all of them have cleared
+    pub resource_finalizers: Vec<String>,
+    /// Resource kinds that can be created, with the schema of their manifests
+    pub kinds: KindRegistry,
 }
 
+/// Raised when a credential fails verification, as opposed to a valid
+/// principal lacking permissions
+#[derive(Debug, thiserror::Error)]
+#[error("invalid credentials: {0}")]
+pub struct InvalidCredentials(pub anyhow::Error);
+
+#[derive(Debug, thiserror::Error)]
+#[error("resource not found")]
+pub struct ResourceNotFound;
+
+#[derive(Debug, thiserror::Error)]
+#[error("resource already exists")]
+pub struct ResourceAlreadyExists;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid argument: {0}")]
+pub struct InvalidArgument(pub anyhow::Error);
+
+/// Raised when a verified principal isn't allowed to do what it asked for
+#[derive(Debug, thiserror::Error)]
+#[error("permission denied: {0}")]
+pub struct PermissionDenied(pub anyhow::Error);
+
+#[derive(Debug, thiserror::Error)]
+#[error("manifest doesn't match the schema of its kind")]
+pub struct InvalidManifest(pub Vec<FieldViolation>);
+
 pub struct Domain {
     pub config: Config,
     pub event_dispatch: EventDispatch,
     pub fabric_state: FabricState,
+    /// Latest revision and manifest submitted for a resource, kept until the
+    /// projection catches up so that changes in quick succession build on
+    /// each other
+    pub pending_revisions: HashMap<ResourceUuid, (Revision, Blob)>,
 }
 
 pub type SignatureValue = String;
 pub type AuthTimestamp = u64;
+pub type Timestamp = u64;
 pub type SecretValue = Vec<u8>;
+pub type ApiKeyId = Vec<u8>;
+pub type MemberKey = Vec<u8>;
 pub type HashDigest = [u8; 32];
 pub type HashSalt = Vec<u8>;
 
 #[derive(Clone)]
 pub enum Credential {
     OwnerSignatureV1(SignatureValue, AuthTimestamp),
+    MemberSignatureV1(MemberKey, SignatureValue, AuthTimestamp),
+    /// Legacy api key without a key id, verified against every live legacy key
+    /// in the namespace
     ApiKeyV1(SecretValue),
+    ApiKeyV2(ApiKeyId, SecretValue),
 }
 
 pub struct RegisterApiKeyCmd {
     pub auth: Credential,
     pub namespace: NamespaceName,
     pub secret: SecretValue,
+    pub scopes: Vec<Scope>,
+    pub not_before: Option<Timestamp>,
+    pub not_after: Option<Timestamp>,
+}
+
+pub struct RegisterApiKeyAck {
+    pub key_id: ApiKeyId,
+}
+
+pub struct RevokeApiKeyCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    /// Key to revoke, empty for the api key making the call
+    pub key_id: ApiKeyId,
+}
+
+pub struct RevokeApiKeyAck {
+    pub event_receipt: Vec<u8>,
+}
+
+pub struct RotateApiKeyCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    /// Key to replace, empty for the api key making the call
+    pub key_id: ApiKeyId,
+    pub secret: SecretValue,
+    pub not_after: Option<Timestamp>,
+    /// Overrides the configured grace period for the key being replaced
+    pub grace_period: Option<u64>,
+}
+
+pub struct AddMemberCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    pub public_key: MemberKey,
+    pub role: Role,
+}
+
+pub struct ChangeMemberRoleCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    pub public_key: MemberKey,
+    pub role: Role,
+}
+
+pub struct RemoveMemberCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    pub public_key: MemberKey,
 }
 
 pub struct CreateResourceCmd {
@@ -50,8 +170,11 @@ pub struct CreateResourceCmd {
     pub name: String,
     pub kind: String,
     pub spec: Blob,
+    pub labels: Labels,
+    pub annotations: Labels,
 }
 
+#[derive(Debug)]
 pub struct CreateResourceAck {
     pub event_receipt: Vec<u8>,
     pub resource_uuid: Vec<u8>,
@@ -59,410 +182,3222 @@ pub struct CreateResourceAck {
 
 pub struct ListResourcesQuery {
     pub auth: Credential,
-    pub resource_name: String,
     pub namespace_name: String,
+    /// Only lists resources whose name starts with the prefix, empty for all
+    pub name_prefix: String,
+    /// Only lists resources of the kind, empty for all
+    pub kind: String,
+    /// Label selector the listed resources have to match, empty for all
+    pub label_selector: String,
+    /// Max number of items in the page, zero for the largest page allowed
+    pub page_size: u32,
+    /// Token returned by a previous page, `None` to start from the first one
+    pub page_token: Option<String>,
+    /// Fills in the spec and the latest status of every item
+    pub include_spec: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceState {
+    Active,
+    /// Deleted, waiting for its finalizers to clear
+    Terminating,
+}
+
+impl ResourceState {
+    fn from_deleting(deleting: bool) -> Self {
+        match deleting {
+            true => ResourceState::Terminating,
+            false => ResourceState::Active,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResourceState::Active => "Active",
+            ResourceState::Terminating => "Terminating",
+        }
+    }
 }
 
+#[derive(Debug)]
 pub struct ListResourcesItem {
+    pub metadata: ResourceMetadataV1,
+    /// Only present when the query asks for it
+    pub spec: Option<Blob>,
+    /// Only present when the query asks for it and a status was reported
+    pub status: Option<Blob>,
+    pub state: ResourceState,
+}
+
+#[derive(Debug)]
+pub struct ListResourcesOutput {
+    pub items: Vec<ListResourcesItem>,
+    /// Token for the next page, `None` once there are no more resources
+    pub next_page_token: Option<String>,
+}
+
+/// Identifies a resource within a namespace
+pub enum ResourceRef {
+    /// Kind and name, which are only unique together
+    Name(String, String),
+    Uuid(ResourceUuid),
+}
+
+impl ResourceRef {
+    fn digest_fields(&self) -> Vec<&[u8]> {
+        match self {
+            ResourceRef::Name(kind, name) => vec![b"name", kind.as_bytes(), name.as_bytes()],
+            ResourceRef::Uuid(x) => vec![b"uuid", x],
+        }
+    }
+}
+
+pub struct PatchResourceCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    pub resource: ResourceRef,
+    pub patch_kind: PatchKind,
+    pub patch: Blob,
+}
+
+#[derive(Debug)]
+pub struct PatchResourceAck {
+    pub event_receipt: Vec<u8>,
+    pub resource_uuid: Vec<u8>,
+    pub revision: Revision,
+}
+
+pub struct DeleteResourceCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    pub resource: ResourceRef,
+}
+
+pub struct DeleteResourceAck {
+    pub event_receipt: Vec<u8>,
+    pub resource_uuid: Vec<u8>,
+}
+
+pub struct ReadResourceQuery {
+    pub auth: Credential,
+    pub namespace_name: String,
+    pub resource: ResourceRef,
+}
+
+#[derive(Debug)]
+pub struct ReadResourceOutput {
     pub metadata: ResourceMetadataV1,
     pub spec: Blob,
-    pub status: Blob,
+    /// Latest status reported for the resource, if any
+    pub status: Option<Blob>,
+    pub revision: Revision,
+    pub state: ResourceState,
 }
 
-pub struct ReadBalanceQuery {
+pub struct ListResourceRevisionsQuery {
     pub auth: Credential,
     pub namespace_name: String,
+    pub resource: ResourceRef,
 }
 
 #[derive(Debug)]
-pub struct ReadBalanceOutput {
-    pub accounts: Vec<(u64, u64, u64)>,
+pub struct ResourceRevisionItem {
+    pub revision: Revision,
+    pub spec: Blob,
+    /// Receipt of the event that produced the revision, if it was recorded
+    pub event_receipt: Option<EventReceipt>,
+    pub timestamp: Option<Timestamp>,
 }
 
-impl Domain {
-    async fn assert_available_namespace(&self, ns: &NamespaceName) -> Result<()> {
-        let exists = self.fabric_state.namespace_exists(&ns).await?;
+pub struct DiffResourceRevisionsQuery {
+    pub auth: Credential,
+    pub namespace_name: String,
+    pub resource: ResourceRef,
+    pub from: Revision,
+    pub to: Revision,
+}
 
-        if exists {
-            bail!("namespace isn't available")
-        }
+pub struct DiffResourceRevisionsOutput {
+    /// JSON patch that turns the `from` manifest into the `to` one
+    pub patch: Blob,
+}
 
-        Ok(())
-    }
+pub struct RollbackResourceCmd {
+    pub auth: Credential,
+    pub namespace: NamespaceName,
+    pub resource: ResourceRef,
+    /// Revision whose manifest becomes the next revision of the resource
+    pub revision: Revision,
+}
 
-    async fn on_namespace_minted(&self, evt: NamespaceMintedV1) -> Result<()> {
-        info!("namespace minted");
+pub struct WatchResourcesQuery {
+    pub auth: Credential,
+    pub namespace_name: String,
+    pub from: ResumeFrom,
+}
 
-        // TODO: how do we handle business invariants? eg, if the namespace isn't
-        // available, then something is inconsistent at a global scale.
-        self.assert_available_namespace(&evt.name).await?;
+/// Subscription to the changes of the resources of a namespace. It reads
+/// the projections on its own, so it can be polled without holding the
+/// domain.
+pub struct ResourceWatch {
+    namespace: NamespaceName,
+    fabric_state: FabricState,
+    pending: VecDeque<EventWrapper>,
+    receiver: broadcast::Receiver<EventWrapper>,
+}
 
-        self.fabric_state.insert_namespace(&evt.name).await?;
+impl ResourceWatch {
+    /// Waits for the next change, `None` once the dispatch is closed. Fails
+    /// with [`ResyncRequired`] if the watch fell behind and missed events.
+    pub async fn next(&mut self) -> Result<Option<WatchResourcesEvent>> {
+        while let Some(event) = self.next_event().await? {
+            if let Some(x) = self.describe(event).await? {
+                return Ok(Some(x));
+            }
+        }
 
-        Ok(())
+        Ok(None)
     }
 
-    async fn assert_existing_namespace(&self, ns: &NamespaceName) -> Result<()> {
-        let exists = self.fabric_state.namespace_exists(&ns).await?;
-
-        if !exists {
-            bail!("invalid namespace")
+    async fn next_event(&mut self) -> Result<Option<EventWrapper>> {
+        if let Some(x) = self.pending.pop_front() {
+            return Ok(Some(x));
         }
 
-        Ok(())
+        match self.receiver.recv().await {
+            Ok(x) => Ok(Some(x)),
+            Err(RecvError::Closed) => Ok(None),
+            Err(RecvError::Lagged(missed)) => {
+                Err(ResyncRequired(format!("watch fell {missed} events behind")).into())
+            }
+        }
     }
 
-    async fn assert_valid_api_key(&self, ns: &NamespaceName, secret: SecretValue) -> Result<()> {
-        let keys = self
-            .fabric_state
-            .get_all_api_keys_for_namespace(&ns)
-            .await?;
-
-        for key in keys {
-            let redigest = auth::digest(&secret, &key.salt)?;
-            let digest = key.digest.as_slice();
+    /// Describes how an event changed a resource of the namespace, `None` if
+    /// the event doesn't concern one
+    async fn describe(&self, event: EventWrapper) -> Result<Option<WatchResourcesEvent>> {
+        let EventWrapper(event, event_receipt, sequence) = event;
 
-            if digest == redigest {
-                return Ok(());
+        let (kind, metadata, spec, status, state) = match event {
+            Event::ResourceCreatedV2(x) => (
+                WatchEventKind::Added,
+                x.metadata,
+                Some(x.manifest),
+                None,
+                ResourceState::Active,
+            ),
+            Event::ResourcePatchedV2(x) => (
+                WatchEventKind::Modified,
+                x.metadata,
+                Some(x.manifest),
+                None,
+                ResourceState::Active,
+            ),
+            Event::ResourceDeletedV1(x) => (
+                WatchEventKind::Deleted,
+                x.metadata,
+                None,
+                None,
+                ResourceState::Terminating,
+            ),
+            Event::ResourceStatusUpdatedV1(x) if x.namespace == self.namespace => {
+                // status events only carry the uuid, the rest comes from the
+                // projection
+                let Some(row) = self
+                    .fabric_state
+                    .get_resource_by_uuid(&x.namespace, &x.resource)
+                    .await?
+                else {
+                    return Ok(None);
+                };
+
+                let state = ResourceState::from_deleting(row.deleting);
+                let metadata = resource_metadata(
+                    &self.fabric_state,
+                    row.namespace,
+                    row.kind,
+                    row.name,
+                    row.uuid,
+                )
+                .await?;
+
+                (
+                    WatchEventKind::Modified,
+                    metadata,
+                    Some(row.manifest),
+                    Some(x.status),
+                    state,
+                )
             }
+            _ => return Ok(None),
+        };
+
+        if metadata.namespace != self.namespace {
+            return Ok(None);
         }
 
-        bail!("invalid api key")
+        Ok(Some(WatchResourcesEvent {
+            kind,
+            sequence,
+            event_receipt,
+            metadata,
+            spec,
+            status,
+            state,
+        }))
     }
+}
 
-    async fn assert_valid_credentials(
-        &self,
-        ns: &NamespaceName,
-        credential: Credential,
-    ) -> Result<()> {
-        match credential {
-            Credential::ApiKeyV1(secret) => self.assert_valid_api_key(ns, secret).await,
-            Credential::OwnerSignatureV1(_, _) => {
-                // TODO
-                Ok(())
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEventKind {
+    Added,
+    Modified,
+    /// Sent once deletion is requested, the resource remains as
+    /// `Terminating` until its finalizers clear
+    Deleted,
+}
+
+impl WatchEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WatchEventKind::Added => "ADDED",
+            WatchEventKind::Modified => "MODIFIED",
+            WatchEventKind::Deleted => "DELETED",
         }
     }
+}
 
-    pub async fn register_apikey(&mut self, cmd: RegisterApiKeyCmd) -> Result<()> {
-        info!("registering apikey");
+#[derive(Debug)]
+pub struct WatchResourcesEvent {
+    pub kind: WatchEventKind,
+    pub sequence: EventSequence,
+    pub event_receipt: EventReceipt,
+    pub metadata: ResourceMetadataV1,
+    pub spec: Option<Blob>,
+    pub status: Option<Blob>,
+    pub state: ResourceState,
+}
 
-        self.assert_existing_namespace(&cmd.namespace).await?;
+pub struct ReadBalanceQuery {
+    pub auth: Credential,
+    pub namespace_name: String,
+}
 
-        self.assert_valid_credentials(&cmd.namespace, cmd.auth)
-            .await?;
+#[derive(Debug)]
+pub struct ReadBalanceOutput {
+    pub accounts: Vec<(u64, u64, u64)>,
+}
 
-        let salt = b"somesaltforyou";
-        let digest = auth::digest(&cmd.secret, salt)?;
+pub struct ReadAuditLogQuery {
+    pub auth: Credential,
+    pub namespace_name: String,
+    /// Cursor returned by a previous page, `None` to start from the newest
+    pub before: Option<i64>,
+    pub page_size: u32,
+}
 
-        self.event_dispatch.submit_event(ApiKeyRegisteredV1 {
-            namespace: cmd.namespace,
-            digest,
-            salt: salt.to_vec(),
-        })?;
+pub struct ReadAuditLogOutput {
+    pub records: Vec<AuditRecord>,
+    /// Cursor for the next page, `None` once the trail is exhausted
+    pub next_cursor: Option<i64>,
+}
 
-        Ok(())
+impl RegisterApiKeyCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest(
+            "register_apikey",
+            &[
+                &self.secret,
+                auth::format_scopes(&self.scopes).as_bytes(),
+                &self.not_before.unwrap_or_default().to_be_bytes(),
+                &self.not_after.unwrap_or_default().to_be_bytes(),
+            ],
+        )
     }
+}
 
-    async fn on_apikey_registered(&mut self, evt: ApiKeyRegisteredV1) -> Result<()> {
-        info!("apikey registered");
+impl RotateApiKeyCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest(
+            "rotate_apikey",
+            &[
+                &self.key_id,
+                &self.secret,
+                &self.not_after.unwrap_or_default().to_be_bytes(),
+                &self.grace_period.unwrap_or_default().to_be_bytes(),
+            ],
+        )
+    }
+}
 
-        self.fabric_state
-            .insert_api_key(&evt.namespace, &evt.digest, &evt.salt)
-            .await?;
+impl RevokeApiKeyCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest("revoke_apikey", &[&self.key_id])
+    }
+}
 
-        Ok(())
+impl AddMemberCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest(
+            "add_member",
+            &[&self.public_key, self.role.as_str().as_bytes()],
+        )
     }
+}
 
-    pub async fn create_resource(&mut self, cmd: CreateResourceCmd) -> Result<CreateResourceAck> {
-        info!("creating resource");
+impl ChangeMemberRoleCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest(
+            "change_member_role",
+            &[&self.public_key, self.role.as_str().as_bytes()],
+        )
+    }
+}
 
-        self.assert_existing_namespace(&cmd.namespace).await?;
+impl RemoveMemberCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest("remove_member", &[&self.public_key])
+    }
+}
 
-        self.assert_valid_credentials(&cmd.namespace, cmd.auth)
-            .await?;
+impl CreateResourceCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest(
+            "create_resource",
+            &[
+                self.name.as_bytes(),
+                self.kind.as_bytes(),
+                &self.spec,
+                &labels::digest_labels(&self.labels),
+                &labels::digest_labels(&self.annotations),
+            ],
+        )
+    }
+}
 
-        // TODO: assert permissions
+impl ListResourcesQuery {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest(
+            "list_resources",
+            &[
+                self.name_prefix.as_bytes(),
+                self.kind.as_bytes(),
+                self.label_selector.as_bytes(),
+                &self.page_size.to_be_bytes(),
+                self.page_token.as_deref().unwrap_or_default().as_bytes(),
+                &[self.include_spec as u8],
+            ],
+        )
+    }
+}
 
-        // assert_resource_type_is_valid(cmd);
-        // assert_resource_manifest_is_valid(cmd);
-        // assert_resource_doesnt_exist(cmd);
+impl PatchResourceCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        let mut fields = self.resource.digest_fields();
+        fields.extend([self.patch_kind.media_type().as_bytes(), &self.patch]);
 
-        // define a new uuid for the resource
-        let resource_uuid = uuid::Uuid::new_v4().into_bytes().to_vec();
+        auth::command_digest("patch_resource", &fields)
+    }
+}
 
-        let event_receipt = self.event_dispatch.submit_event(ResourceCreatedV1 {
-            metadata: ResourceMetadataV1 {
-                namespace: cmd.namespace,
-                kind: cmd.kind,
-                name: cmd.name,
-                uuid: resource_uuid.clone(),
-            },
-            manifest: cmd.spec,
-        })?;
+impl DeleteResourceCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest("delete_resource", &self.resource.digest_fields())
+    }
+}
 
-        let ack = CreateResourceAck {
-            event_receipt,
-            resource_uuid,
-        };
+impl ReadResourceQuery {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest("read_resource", &self.resource.digest_fields())
+    }
+}
 
-        Ok(ack)
+impl ListResourceRevisionsQuery {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest("list_resource_revisions", &self.resource.digest_fields())
     }
+}
 
-    async fn on_resource_created(&mut self, evt: ResourceCreatedV1) -> Result<()> {
-        info!("resource created");
+impl DiffResourceRevisionsQuery {
+    pub fn command_digest(&self) -> HashDigest {
+        let (from, to) = (self.from.to_be_bytes(), self.to.to_be_bytes());
 
-        self.fabric_state
-            .insert_resource(
-                &evt.metadata.namespace,
-                &evt.metadata.kind,
-                &evt.metadata.uuid,
-                &evt.metadata.name,
-                &evt.manifest,
-            )
-            .await?;
+        let mut fields = self.resource.digest_fields();
+        fields.extend([from.as_slice(), &to]);
 
-        Ok(())
+        auth::command_digest("diff_resource_revisions", &fields)
     }
+}
 
-    pub async fn list_resources(
-        &self,
-        query: ListResourcesQuery,
-    ) -> Result<Vec<ListResourcesItem>> {
-        self.assert_existing_namespace(&query.namespace_name)
-            .await?;
+impl RollbackResourceCmd {
+    pub fn command_digest(&self) -> HashDigest {
+        let revision = self.revision.to_be_bytes();
 
-        // assert_namespace_read_access(query);
-        self.assert_valid_credentials(&query.namespace_name, query.auth)
-            .await?;
+        let mut fields = self.resource.digest_fields();
+        fields.push(&revision);
 
-        let items = self
-            .fabric_state
-            .list_resources(&query.namespace_name)
-            .await?
-            .into_iter()
-            .map(|x| ListResourcesItem {
-                metadata: ResourceMetadataV1 {
-                    namespace: query.namespace_name.clone(),
-                    kind: x.kind,
-                    name: x.name,
-                    uuid: x.uuid,
-                },
-                spec: vec![],
-                status: vec![],
-            })
-            .collect();
+        auth::command_digest("rollback_resource", &fields)
+    }
+}
 
-        Ok(items)
+impl WatchResourcesQuery {
+    pub fn command_digest(&self) -> HashDigest {
+        let sequence;
+
+        let fields: [&[u8]; 2] = match &self.from {
+            ResumeFrom::Now => [b"now", b""],
+            ResumeFrom::Sequence(x) => {
+                sequence = x.to_be_bytes();
+                [b"sequence", &sequence]
+            }
+            ResumeFrom::Receipt(x) => [b"receipt", x],
+        };
+
+        auth::command_digest("watch_resources", &fields)
     }
+}
 
-    pub async fn read_balance(&self, query: ReadBalanceQuery) -> Result<ReadBalanceOutput> {
-        self.assert_existing_namespace(&query.namespace_name)
-            .await?;
+impl ReadBalanceQuery {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest("read_balance", &[])
+    }
+}
 
-        // assert_namespace_balance_access(query);
-        self.assert_valid_credentials(&query.namespace_name, query.auth)
-            .await?;
+impl ReadAuditLogQuery {
+    pub fn command_digest(&self) -> HashDigest {
+        auth::command_digest(
+            "read_audit_log",
+            &[
+                &self.before.unwrap_or_default().to_be_bytes(),
+                &self.page_size.to_be_bytes(),
+            ],
+        )
+    }
+}
 
-        let accounts = self
-            .fabric_state
-            .read_balance(&query.namespace_name)
-            .await?
-            .into_iter()
-            .map(|(a, b, c)| (a as u64, b as u64, c as u64))
-            .collect();
+const MAX_AUDIT_PAGE_SIZE: u32 = 100;
+const MAX_RESOURCE_PAGE_SIZE: u32 = 100;
+/// Logged events read at a time when catching up
+const REPLAY_BATCH_SIZE: i64 = 500;
 
-        Ok(ReadBalanceOutput { accounts })
+/// Encodes a listing position as an opaque token for clients to send back
+fn encode_page_token(cursor: &ResourceCursor) -> String {
+    let json = serde_json::json!([cursor.name, cursor.kind]).to_string();
+    URL_SAFE_NO_PAD.encode(json)
+}
+
+fn decode_page_token(token: &str) -> Result<ResourceCursor> {
+    let json = URL_SAFE_NO_PAD
+        .decode(token)
+        .map_err(|_| InvalidArgument(anyhow!("invalid page token")))?;
+
+    let (name, kind) = serde_json::from_slice(&json)
+        .map_err(|_| InvalidArgument(anyhow!("invalid page token")))?;
+
+    Ok(ResourceCursor { name, kind })
+}
+
+/// Resolves the key targeted by a command, where an empty id stands for the api
+/// key making the call. Legacy tokens don't carry their id, so that's how their
+/// holders get to address them.
+fn target_api_key(key_id: ApiKeyId, principal: &Principal) -> Result<ApiKeyId> {
+    if !key_id.is_empty() {
+        return Ok(key_id);
     }
 
-    async fn on_resource_usage(&mut self, evt: ResourceUsageV1) -> Result<()> {
-        info!("resource usage");
+    match principal {
+        Principal::ApiKey(Some(x), _) => Ok(x.clone()),
+        _ => Err(
+            InvalidArgument(anyhow!("key id is required unless calling with an api key")).into(),
+        ),
+    }
+}
 
-        self.fabric_state
-            .insert_accounting(
-                evt.epoch as i64,
-                &evt.entry,
-                &evt.cluster,
+/// Completes the metadata of a resource with its labels and annotations
+async fn resource_metadata(
+    fabric_state: &FabricState,
+    namespace: NamespaceName,
+    kind: String,
+    name: String,
+    uuid: ResourceUuid,
+) -> Result<ResourceMetadataV1> {
+    let labels = fabric_state.get_resource_labels(&uuid).await?;
+    let annotations = fabric_state.get_resource_annotations(&uuid).await?;
+
+    Ok(ResourceMetadataV1 {
+        namespace,
+        kind,
+        name,
+        uuid,
+        labels,
+        annotations,
+    })
+}
+
+impl Domain {
+    /// Records the outcome of a command or query. Failing to write the record
+    /// doesn't fail the operation itself.
+    async fn audit<T>(&self, trail: AuditTrail, result: &Result<T>) {
+        let record = trail.into_record(result);
+
+        if let Err(error) = self.fabric_state.insert_audit_record(&record).await {
+            warn!(?error, ?record, "failed to write audit record");
+        }
+    }
+    async fn on_namespace_minted(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: NamespaceMintedV1,
+    ) -> Result<()> {
+        info!("namespace minted");
+
+        // TODO: how do we handle business invariants? eg, if the namespace isn't
+        // available, then something is inconsistent at a global scale.
+        let inserted = self
+            .fabric_state
+            .insert_namespace(tx, &evt.name, &evt.root_public_key)
+            .await?;
+
+        if !inserted {
+            bail!("namespace isn't available")
+        }
+
+        Ok(())
+    }
+
+    async fn assert_existing_namespace(&self, ns: &NamespaceName) -> Result<()> {
+        let exists = self.fabric_state.namespace_exists(&ns).await?;
+
+        if !exists {
+            bail!("invalid namespace")
+        }
+
+        Ok(())
+    }
+
+    async fn assert_valid_api_key(
+        &self,
+        ns: &NamespaceName,
+        key_id: ApiKeyId,
+        secret: SecretValue,
+    ) -> Result<Principal> {
+        let key = self
+            .fabric_state
+            .get_api_key(ns, &key_id)
+            .await?
+            .ok_or_else(|| anyhow!("invalid api key"))?;
+
+        if key.revoked {
+            bail!("api key has been revoked")
+        }
+
+        auth::assert_within_validity(
+            auth::unix_now()?,
+            key.validity.not_before.map(|x| x as u64),
+            key.validity.not_after.map(|x| x as u64),
+        )?;
+
+        let redigest = auth::digest(&secret, &key.salt, &key.hashing.into())?;
+
+        if !auth::digest_matches(&key.digest, &redigest) {
+            bail!("invalid api key")
+        }
+
+        Ok(Principal::ApiKey(key.key_id, key.scopes))
+    }
+
+    /// Verifies a token that only carries a secret against the legacy keys of
+    /// the namespace. Only live keys get hashed, so the cost of this goes away
+    /// as legacy keys are rotated into regular ones.
+    async fn assert_valid_legacy_api_key(
+        &self,
+        ns: &NamespaceName,
+        secret: SecretValue,
+    ) -> Result<Principal> {
+        let keys = self
+            .fabric_state
+            .get_live_legacy_api_keys(ns, auth::unix_now()? as i64)
+            .await?;
+
+        for key in keys {
+            let redigest = auth::digest(&secret, &key.salt, &key.hashing.into())?;
+
+            if auth::digest_matches(&key.digest, &redigest) {
+                return Ok(Principal::ApiKey(key.key_id, key.scopes));
+            }
+        }
+
+        bail!("invalid api key")
+    }
+
+    async fn assert_valid_owner_signature(
+        &self,
+        ns: &NamespaceName,
+        signature: SignatureValue,
+        timestamp: AuthTimestamp,
+        cmd: &HashDigest,
+    ) -> Result<Principal> {
+        let root_key = self
+            .fabric_state
+            .get_namespace_root_key(ns)
+            .await?
+            .ok_or_else(|| anyhow!("namespace has no root key"))?;
+
+        auth::assert_fresh_timestamp(timestamp)?;
+
+        let payload = auth::signature_payload(ns, cmd, timestamp);
+        auth::verify_signature(&root_key, &signature, &payload)?;
+        self.assert_unused_signature(&signature, timestamp).await?;
+
+        Ok(Principal::RootOwner)
+    }
+
+    /// Records a verified signature, failing if it was already used. They're
+    /// kept until their timestamp is too stale to pass the freshness check.
+    async fn assert_unused_signature(
+        &self,
+        signature: &SignatureValue,
+        timestamp: AuthTimestamp,
+    ) -> Result<()> {
+        let stale_before = auth::unix_now()?.saturating_sub(auth::MAX_SIGNATURE_SKEW);
+
+        // the encoding is case insensitive, the signature itself isn't
+        // malleable since it's checked with verify_strict
+        let unused = self
+            .fabric_state
+            .insert_seen_signature(
+                &signature.to_ascii_lowercase(),
+                timestamp as i64,
+                stale_before as i64,
+            )
+            .await?;
+
+        if !unused {
+            bail!("signature already used")
+        }
+
+        Ok(())
+    }
+
+    async fn assert_valid_member_signature(
+        &self,
+        ns: &NamespaceName,
+        public_key: MemberKey,
+        signature: SignatureValue,
+        timestamp: AuthTimestamp,
+        cmd: &HashDigest,
+    ) -> Result<Principal> {
+        let role: Role = self
+            .fabric_state
+            .get_member_role(ns, &public_key)
+            .await?
+            .ok_or_else(|| anyhow!("not a member of the namespace"))?
+            .parse()?;
+
+        auth::assert_fresh_timestamp(timestamp)?;
+
+        let payload = auth::signature_payload(ns, cmd, timestamp);
+        auth::verify_signature(&public_key, &signature, &payload)?;
+        self.assert_unused_signature(&signature, timestamp).await?;
+
+        Ok(Principal::Member(public_key, role))
+    }
+
+    /// Verifies a credential and resolves the principal behind it
+    ///
+    /// This doesn't check permissions, callers are expected to authorize the
+    /// returned principal for the operation at hand.
+    async fn assert_valid_credentials(
+        &self,
+        ns: &NamespaceName,
+        credential: Credential,
+        cmd: &HashDigest,
+    ) -> Result<Principal> {
+        let principal = match credential {
+            Credential::ApiKeyV1(secret) => self.assert_valid_legacy_api_key(ns, secret).await,
+            Credential::ApiKeyV2(key_id, secret) => {
+                self.assert_valid_api_key(ns, key_id, secret).await
+            }
+            Credential::OwnerSignatureV1(signature, timestamp) => {
+                self.assert_valid_owner_signature(ns, signature, timestamp, cmd)
+                    .await
+            }
+            Credential::MemberSignatureV1(public_key, signature, timestamp) => {
+                self.assert_valid_member_signature(ns, public_key, signature, timestamp, cmd)
+                    .await
+            }
+        };
+
+        principal.map_err(|err| InvalidCredentials(err).into())
+    }
+
+    async fn issue_apikey(
+        &mut self,
+        namespace: NamespaceName,
+        secret: &SecretValue,
+        scopes: Vec<Scope>,
+        not_before: Option<Timestamp>,
+        not_after: Option<Timestamp>,
+    ) -> Result<ApiKeyId> {
+        if scopes.is_empty() {
+            bail!("api key needs at least one scope")
+        }
+
+        if let (Some(a), Some(b)) = (not_before, not_after) {
+            if a >= b {
+                bail!("api key validity window is empty")
+            }
+        }
+
+        let key_id = auth::random_key_id();
+        let salt = auth::random_salt();
+        let hash_params = self.config.apikey_hashing.clone();
+        let digest = auth::digest(secret, &salt, &hash_params)?;
+
+        self.event_dispatch
+            .submit_event(ApiKeyRegisteredV1 {
+                namespace,
+                key_id: key_id.clone(),
+                digest,
+                salt,
+                hash_params,
+                scopes,
+                not_before,
+                not_after,
+            })
+            .await?;
+
+        Ok(key_id)
+    }
+
+    pub async fn register_apikey(&mut self, cmd: RegisterApiKeyCmd) -> Result<RegisterApiKeyAck> {
+        let trail = AuditTrail::new("register_apikey", &cmd.namespace, &cmd.auth);
+        let result = self.try_register_apikey(cmd).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_register_apikey(&mut self, cmd: RegisterApiKeyCmd) -> Result<RegisterApiKeyAck> {
+        info!("registering apikey");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ApiKeysWrite)?;
+        principal.authorize_all(&cmd.scopes)?;
+
+        let key_id = self
+            .issue_apikey(
+                cmd.namespace,
+                &cmd.secret,
+                cmd.scopes,
+                cmd.not_before,
+                cmd.not_after,
+            )
+            .await?;
+
+        Ok(RegisterApiKeyAck { key_id })
+    }
+
+    /// Issues a replacement for an existing api key
+    ///
+    /// The old key keeps working until the grace period elapses so that
+    /// clients can switch over without downtime.
+    pub async fn rotate_apikey(&mut self, cmd: RotateApiKeyCmd) -> Result<RegisterApiKeyAck> {
+        let trail = AuditTrail::new("rotate_apikey", &cmd.namespace, &cmd.auth);
+        let result = self.try_rotate_apikey(cmd).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_rotate_apikey(&mut self, cmd: RotateApiKeyCmd) -> Result<RegisterApiKeyAck> {
+        info!("rotating apikey");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ApiKeysWrite)?;
+
+        let old_key_id = target_api_key(cmd.key_id, &principal)?;
+
+        let old = self
+            .fabric_state
+            .get_api_key(&cmd.namespace, &old_key_id)
+            .await?
+            .ok_or_else(|| anyhow!("invalid api key"))?;
+
+        if old.revoked {
+            bail!("api key has been revoked")
+        }
+
+        let grace = cmd
+            .grace_period
+            .unwrap_or(self.config.apikey_rotation_grace);
+        let grace_end = auth::unix_now()? + grace;
+
+        // never extend the lifetime of the old key
+        let not_after = match old.validity.not_after {
+            Some(x) => grace_end.min(x as u64),
+            None => grace_end,
+        };
+
+        // the replacement inherits the permissions of the key it replaces
+        let scopes = match old.scopes.as_deref() {
+            Some(x) => auth::parse_scopes(x)?,
+            None => Scope::ALL.to_vec(),
+        };
+        principal.authorize_all(&scopes)?;
+
+        let key_id = self
+            .issue_apikey(
+                cmd.namespace.clone(),
+                &cmd.secret,
+                scopes,
+                None,
+                cmd.not_after,
+            )
+            .await?;
+
+        self.event_dispatch
+            .submit_event(ApiKeyRotatedV1 {
+                namespace: cmd.namespace,
+                key_id: old_key_id,
+                replaced_by: key_id.clone(),
+                not_after,
+            })
+            .await?;
+
+        Ok(RegisterApiKeyAck { key_id })
+    }
+
+    async fn on_apikey_registered(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ApiKeyRegisteredV1,
+    ) -> Result<()> {
+        info!("apikey registered");
+
+        self.fabric_state
+            .insert_api_key(
+                tx,
                 &evt.namespace,
-                Some(&evt.resource),
-                vec![
-                    AccountDelta {
-                        account: 1,
-                        debit: Some(evt.units as i64),
-                        credit: None,
-                    },
-                    AccountDelta {
-                        account: 2,
-                        debit: None,
-                        credit: Some(evt.units as i64),
+                &ApiKey {
+                    key_id: Some(evt.key_id),
+                    digest: evt.digest.to_vec(),
+                    salt: evt.salt,
+                    hashing: (&evt.hash_params).into(),
+                    validity: ApiKeyValidity {
+                        not_before: evt.not_before.map(|x| x as i64),
+                        not_after: evt.not_after.map(|x| x as i64),
                     },
-                ],
+                    scopes: Some(auth::format_scopes(&evt.scopes)),
+                    revoked: false,
+                    legacy: false,
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn on_apikey_rotated(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ApiKeyRotatedV1,
+    ) -> Result<()> {
+        info!("apikey rotated");
+
+        self.fabric_state
+            .update_api_key_expiry(tx, &evt.namespace, &evt.key_id, evt.not_after as i64)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_apikey(&mut self, cmd: RevokeApiKeyCmd) -> Result<RevokeApiKeyAck> {
+        let trail = AuditTrail::new("revoke_apikey", &cmd.namespace, &cmd.auth);
+        let result = self.try_revoke_apikey(cmd).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_revoke_apikey(&mut self, cmd: RevokeApiKeyCmd) -> Result<RevokeApiKeyAck> {
+        info!("revoking apikey");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ApiKeysWrite)?;
+
+        let key_id = target_api_key(cmd.key_id, &principal)?;
+
+        let key = self
+            .fabric_state
+            .get_api_key(&cmd.namespace, &key_id)
+            .await?;
+
+        match key {
+            None => bail!("invalid api key"),
+            Some(key) if key.revoked => bail!("api key already revoked"),
+            _ => (),
+        };
+
+        let event_receipt = self
+            .event_dispatch
+            .submit_event(ApiKeyRevokedV1 {
+                namespace: cmd.namespace,
+                key_id,
+            })
+            .await?;
+
+        Ok(RevokeApiKeyAck { event_receipt })
+    }
+
+    async fn on_apikey_revoked(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ApiKeyRevokedV1,
+    ) -> Result<()> {
+        info!("apikey revoked");
+
+        self.fabric_state
+            .revoke_api_key(tx, &evt.namespace, &evt.key_id)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn add_member(&mut self, cmd: AddMemberCmd) -> Result<()> {
+        let trail = AuditTrail::new("add_member", &cmd.namespace, &cmd.auth);
+        let result = self.try_add_member(cmd).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_add_member(&mut self, cmd: AddMemberCmd) -> Result<()> {
+        info!("adding member");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::MembersWrite)?;
+
+        if cmd.role == Role::Owner && !principal.is_owner() {
+            return Err(PermissionDenied(anyhow!("only owners can grant the owner role")).into());
+        }
+
+        auth::assert_valid_public_key(&cmd.public_key)?;
+
+        let existing = self
+            .fabric_state
+            .get_member_role(&cmd.namespace, &cmd.public_key)
+            .await?;
+
+        if existing.is_some() {
+            bail!("already a member of the namespace")
+        }
+
+        self.event_dispatch
+            .submit_event(MemberAddedV1 {
+                namespace: cmd.namespace,
+                public_key: cmd.public_key,
+                role: cmd.role,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn on_member_added(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: MemberAddedV1,
+    ) -> Result<()> {
+        info!("member added");
+
+        self.fabric_state
+            .insert_member(tx, &evt.namespace, &evt.public_key, evt.role.as_str())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_existing_member_role(&self, ns: &NamespaceName, key: &MemberKey) -> Result<Role> {
+        self.fabric_state
+            .get_member_role(ns, key)
+            .await?
+            .ok_or_else(|| anyhow!("not a member of the namespace"))?
+            .parse()
+    }
+
+    pub async fn change_member_role(&mut self, cmd: ChangeMemberRoleCmd) -> Result<()> {
+        let trail = AuditTrail::new("change_member_role", &cmd.namespace, &cmd.auth);
+        let result = self.try_change_member_role(cmd).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_change_member_role(&mut self, cmd: ChangeMemberRoleCmd) -> Result<()> {
+        info!("changing member role");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::MembersWrite)?;
+
+        let current = self
+            .get_existing_member_role(&cmd.namespace, &cmd.public_key)
+            .await?;
+
+        if (current == Role::Owner || cmd.role == Role::Owner) && !principal.is_owner() {
+            let err = anyhow!("only owners can grant or take the owner role");
+            return Err(PermissionDenied(err).into());
+        }
+
+        self.event_dispatch
+            .submit_event(MemberRoleChangedV1 {
+                namespace: cmd.namespace,
+                public_key: cmd.public_key,
+                role: cmd.role,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn on_member_role_changed(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: MemberRoleChangedV1,
+    ) -> Result<()> {
+        info!("member role changed");
+
+        self.fabric_state
+            .update_member_role(tx, &evt.namespace, &evt.public_key, evt.role.as_str())
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_member(&mut self, cmd: RemoveMemberCmd) -> Result<()> {
+        let trail = AuditTrail::new("remove_member", &cmd.namespace, &cmd.auth);
+        let result = self.try_remove_member(cmd).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_remove_member(&mut self, cmd: RemoveMemberCmd) -> Result<()> {
+        info!("removing member");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::MembersWrite)?;
+
+        let current = self
+            .get_existing_member_role(&cmd.namespace, &cmd.public_key)
+            .await?;
+
+        if current == Role::Owner && !principal.is_owner() {
+            return Err(PermissionDenied(anyhow!("only owners can remove other owners")).into());
+        }
+
+        self.event_dispatch
+            .submit_event(MemberRemovedV1 {
+                namespace: cmd.namespace,
+                public_key: cmd.public_key,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn on_member_removed(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: MemberRemovedV1,
+    ) -> Result<()> {
+        info!("member removed");
+
+        self.fabric_state
+            .delete_member(tx, &evt.namespace, &evt.public_key)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn create_resource(&mut self, cmd: CreateResourceCmd) -> Result<CreateResourceAck> {
+        let mut trail = AuditTrail::new("create_resource", &cmd.namespace, &cmd.auth);
+        let result = self.try_create_resource(cmd).await;
+
+        if let Ok(ack) = &result {
+            trail.set_resource_uuid(&ack.resource_uuid);
+        }
+
+        self.audit(trail, &result).await;
+        result
+    }
+
+    fn assert_resource_type_is_valid(&self, kind: &str) -> Result<()> {
+        if !self.config.kinds.contains(kind) {
+            return Err(InvalidArgument(anyhow!("unknown resource kind {kind}")).into());
+        }
+
+        Ok(())
+    }
+
+    fn assert_resource_manifest_is_valid(&self, kind: &str, manifest: &[u8]) -> Result<()> {
+        let manifest: serde_json::Value = serde_json::from_slice(manifest)
+            .map_err(|err| InvalidArgument(anyhow!("manifest isn't valid json: {err}")))?;
+
+        let violations = self
+            .config
+            .kinds
+            .validate(kind, &manifest)
+            .map_err(InvalidArgument)?;
+
+        if !violations.is_empty() {
+            return Err(InvalidManifest(violations).into());
+        }
+
+        Ok(())
+    }
+
+    async fn assert_resource_doesnt_exist(
+        &self,
+        ns: &NamespaceName,
+        kind: &str,
+        name: &str,
+    ) -> Result<()> {
+        if self.fabric_state.resource_exists(ns, kind, name).await? {
+            return Err(ResourceAlreadyExists.into());
+        }
+
+        Ok(())
+    }
+
+    async fn try_create_resource(&mut self, cmd: CreateResourceCmd) -> Result<CreateResourceAck> {
+        info!("creating resource");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesWrite)?;
+
+        self.assert_resource_type_is_valid(&cmd.kind)?;
+        self.assert_resource_manifest_is_valid(&cmd.kind, &cmd.spec)?;
+        labels::validate_labels(&cmd.labels, &cmd.annotations).map_err(InvalidArgument)?;
+        self.assert_resource_doesnt_exist(&cmd.namespace, &cmd.kind, &cmd.name)
+            .await?;
+
+        // define a new uuid for the resource
+        let resource_uuid = uuid::Uuid::new_v4().into_bytes().to_vec();
+
+        let event_receipt = self
+            .event_dispatch
+            .submit_event(ResourceCreatedV2 {
+                metadata: ResourceMetadataV1 {
+                    namespace: cmd.namespace,
+                    kind: cmd.kind,
+                    name: cmd.name,
+                    uuid: resource_uuid.clone(),
+                    labels: cmd.labels,
+                    annotations: cmd.annotations,
+                },
+                manifest: cmd.spec,
+                timestamp: Some(auth::unix_now()?),
+            })
+            .await?;
+
+        let ack = CreateResourceAck {
+            event_receipt,
+            resource_uuid,
+        };
+
+        Ok(ack)
+    }
+
+    async fn record_resource_revision(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        uuid: ResourceUuid,
+        revision: Revision,
+        manifest: Blob,
+        receipt: EventReceipt,
+        timestamp: Option<Timestamp>,
+    ) -> Result<()> {
+        self.fabric_state
+            .insert_resource_revision(
+                tx,
+                &ResourceRevision {
+                    resource: uuid,
+                    revision: revision as i64,
+                    manifest,
+                    event_receipt: Some(receipt),
+                    timestamp: timestamp.map(|x| x as i64),
+                },
+            )
+            .await
+    }
+
+    async fn on_resource_created(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ResourceCreatedV2,
+        receipt: EventReceipt,
+    ) -> Result<()> {
+        info!("resource created");
+
+        let inserted = self
+            .fabric_state
+            .insert_resource(
+                tx,
+                &evt.metadata.namespace,
+                &evt.metadata.kind,
+                &evt.metadata.uuid,
+                &evt.metadata.name,
+                &evt.manifest,
+            )
+            .await?;
+
+        // a concurrent create for the same name can get past the command check
+        // before either event is projected, the first one to land wins
+        if !inserted {
+            warn!(name = evt.metadata.name, "rejecting duplicate resource");
+
+            return self
+                .fabric_state
+                .insert_rejected_resource(
+                    tx,
+                    &evt.metadata.namespace,
+                    &evt.metadata.kind,
+                    &evt.metadata.uuid,
+                    &evt.metadata.name,
+                    &receipt,
+                )
+                .await;
+        }
+
+        self.fabric_state
+            .insert_resource_labels(
+                tx,
+                &evt.metadata.uuid,
+                &evt.metadata.labels,
+                &evt.metadata.annotations,
+            )
+            .await?;
+
+        self.record_resource_revision(
+            tx,
+            evt.metadata.uuid,
+            1,
+            evt.manifest,
+            receipt,
+            evt.timestamp,
+        )
+        .await
+    }
+
+    pub async fn list_resources(&self, query: ListResourcesQuery) -> Result<ListResourcesOutput> {
+        let trail = AuditTrail::new("list_resources", &query.namespace_name, &query.auth);
+        let result = self.try_list_resources(query).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_list_resources(&self, query: ListResourcesQuery) -> Result<ListResourcesOutput> {
+        self.assert_existing_namespace(&query.namespace_name)
+            .await?;
+
+        let digest = query.command_digest();
+        let principal = self
+            .assert_valid_credentials(&query.namespace_name, query.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesRead)?;
+
+        let non_empty = |x: String| Some(x).filter(|x| !x.is_empty());
+
+        let filter = ResourceFilter {
+            kind: non_empty(query.kind),
+            name_prefix: non_empty(query.name_prefix),
+            selector: query.label_selector.parse().map_err(InvalidArgument)?,
+        };
+
+        let after = query
+            .page_token
+            .as_deref()
+            .map(decode_page_token)
+            .transpose()?;
+
+        let page_size = match query.page_size {
+            0 => MAX_RESOURCE_PAGE_SIZE,
+            x => x.min(MAX_RESOURCE_PAGE_SIZE),
+        };
+
+        // one extra row tells whether there's a page after this one
+        let mut rows = self
+            .fabric_state
+            .list_resources(
+                &query.namespace_name,
+                &filter,
+                after.as_ref(),
+                page_size as i64 + 1,
+            )
+            .await?;
+
+        let next_page_token = match rows.len() > page_size as usize {
+            true => {
+                rows.truncate(page_size as usize);
+                rows.last().map(|x| {
+                    encode_page_token(&ResourceCursor {
+                        name: x.name.clone(),
+                        kind: x.kind.clone(),
+                    })
+                })
+            }
+            false => None,
+        };
+
+        let mut items = Vec::with_capacity(rows.len());
+
+        for x in rows {
+            let metadata = resource_metadata(
+                &self.fabric_state,
+                query.namespace_name.clone(),
+                x.kind,
+                x.name,
+                x.uuid,
+            )
+            .await?;
+
+            let (spec, status) = match query.include_spec {
+                true => (Some(x.manifest), x.status),
+                false => (None, None),
+            };
+
+            items.push(ListResourcesItem {
+                metadata,
+                spec,
+                status,
+                state: ResourceState::from_deleting(x.deleting),
+            });
+        }
+
+        Ok(ListResourcesOutput {
+            items,
+            next_page_token,
+        })
+    }
+
+    async fn get_existing_resource(
+        &self,
+        ns: &NamespaceName,
+        resource: &ResourceRef,
+    ) -> Result<ResourceRow> {
+        let row = match resource {
+            ResourceRef::Name(kind, name) => {
+                self.fabric_state
+                    .get_resource_by_name(ns, kind, name)
+                    .await?
+            }
+            ResourceRef::Uuid(x) => self.fabric_state.get_resource_by_uuid(ns, x).await?,
+        };
+
+        Ok(row.ok_or(ResourceNotFound)?)
+    }
+
+    /// Same as `get_existing_resource`, but reflecting changes that were
+    /// submitted and aren't projected yet
+    async fn get_latest_resource(
+        &mut self,
+        ns: &NamespaceName,
+        resource: &ResourceRef,
+    ) -> Result<ResourceRow> {
+        let mut row = self.get_existing_resource(ns, resource).await?;
+
+        match self.pending_revisions.get(&row.uuid) {
+            Some((revision, manifest)) if *revision > row.revision as Revision => {
+                row.revision = *revision as i64;
+                row.manifest = manifest.clone();
+            }
+            _ => {
+                self.pending_revisions.remove(&row.uuid);
+            }
+        }
+
+        Ok(row)
+    }
+
+    pub async fn patch_resource(&mut self, cmd: PatchResourceCmd) -> Result<PatchResourceAck> {
+        let mut trail = AuditTrail::new("patch_resource", &cmd.namespace, &cmd.auth);
+        let result = self.try_patch_resource(cmd, &mut trail).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_patch_resource(
+        &mut self,
+        cmd: PatchResourceCmd,
+        trail: &mut AuditTrail,
+    ) -> Result<PatchResourceAck> {
+        info!("patching resource");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesWrite)?;
+
+        let row = self
+            .get_latest_resource(&cmd.namespace, &cmd.resource)
+            .await?;
+        trail.set_resource_uuid(&row.uuid);
+
+        if row.deleting {
+            bail!("resource is being deleted")
+        }
+
+        let manifest = patch::apply_patch(&row.manifest, cmd.patch_kind, &cmd.patch)
+            .map_err(InvalidArgument)?;
+
+        self.submit_resource_manifest(row, manifest).await
+    }
+
+    /// Validates a manifest and emits it as the next revision of the resource
+    async fn submit_resource_manifest(
+        &mut self,
+        row: ResourceRow,
+        manifest: Blob,
+    ) -> Result<PatchResourceAck> {
+        self.assert_resource_type_is_valid(&row.kind)?;
+        self.assert_resource_manifest_is_valid(&row.kind, &manifest)?;
+
+        let revision = row.revision as Revision + 1;
+        let resource_uuid = row.uuid.clone();
+
+        let metadata = resource_metadata(
+            &self.fabric_state,
+            row.namespace,
+            row.kind,
+            row.name,
+            row.uuid,
+        )
+        .await?;
+
+        let event_receipt = self
+            .event_dispatch
+            .submit_event(ResourcePatchedV2 {
+                metadata,
+                manifest: manifest.clone(),
+                revision,
+                timestamp: Some(auth::unix_now()?),
+            })
+            .await?;
+
+        self.pending_revisions
+            .insert(resource_uuid.clone(), (revision, manifest));
+
+        Ok(PatchResourceAck {
+            event_receipt,
+            resource_uuid,
+            revision,
+        })
+    }
+
+    async fn on_resource_patched(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ResourcePatchedV2,
+        receipt: EventReceipt,
+    ) -> Result<()> {
+        info!("resource patched");
+
+        let updated = self
+            .fabric_state
+            .update_resource_manifest(
+                tx,
+                &evt.metadata.namespace,
+                &evt.metadata.uuid,
+                &evt.manifest,
+                evt.revision as i64,
+            )
+            .await?;
+
+        if !updated {
+            warn!(
+                revision = evt.revision,
+                "ignoring patch for a stale revision"
+            );
+            return Ok(());
+        }
+
+        self.record_resource_revision(
+            tx,
+            evt.metadata.uuid,
+            evt.revision,
+            evt.manifest,
+            receipt,
+            evt.timestamp,
+        )
+        .await
+    }
+
+    pub async fn list_resource_revisions(
+        &self,
+        query: ListResourceRevisionsQuery,
+    ) -> Result<Vec<ResourceRevisionItem>> {
+        let trail = AuditTrail::new(
+            "list_resource_revisions",
+            &query.namespace_name,
+            &query.auth,
+        );
+        let result = self.try_list_resource_revisions(query).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_list_resource_revisions(
+        &self,
+        query: ListResourceRevisionsQuery,
+    ) -> Result<Vec<ResourceRevisionItem>> {
+        self.assert_existing_namespace(&query.namespace_name)
+            .await?;
+
+        let digest = query.command_digest();
+        let principal = self
+            .assert_valid_credentials(&query.namespace_name, query.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesRead)?;
+
+        let row = self
+            .get_existing_resource(&query.namespace_name, &query.resource)
+            .await?;
+
+        let items = self
+            .fabric_state
+            .list_resource_revisions(&row.uuid)
+            .await?
+            .into_iter()
+            .map(|x| ResourceRevisionItem {
+                revision: x.revision as Revision,
+                spec: x.manifest,
+                event_receipt: x.event_receipt,
+                timestamp: x.timestamp.map(|x| x as Timestamp),
+            })
+            .collect();
+
+        Ok(items)
+    }
+
+    async fn get_existing_revision(
+        &self,
+        uuid: &ResourceUuid,
+        revision: Revision,
+    ) -> Result<ResourceRevision> {
+        let row = self
+            .fabric_state
+            .get_resource_revision(uuid, revision as i64)
+            .await?;
+
+        let row =
+            row.ok_or_else(|| InvalidArgument(anyhow!("revision {revision} doesn't exist")))?;
+
+        Ok(row)
+    }
+
+    pub async fn diff_resource_revisions(
+        &self,
+        query: DiffResourceRevisionsQuery,
+    ) -> Result<DiffResourceRevisionsOutput> {
+        let trail = AuditTrail::new(
+            "diff_resource_revisions",
+            &query.namespace_name,
+            &query.auth,
+        );
+        let result = self.try_diff_resource_revisions(query).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_diff_resource_revisions(
+        &self,
+        query: DiffResourceRevisionsQuery,
+    ) -> Result<DiffResourceRevisionsOutput> {
+        self.assert_existing_namespace(&query.namespace_name)
+            .await?;
+
+        let digest = query.command_digest();
+        let principal = self
+            .assert_valid_credentials(&query.namespace_name, query.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesRead)?;
+
+        let row = self
+            .get_existing_resource(&query.namespace_name, &query.resource)
+            .await?;
+
+        let from = self.get_existing_revision(&row.uuid, query.from).await?;
+        let to = self.get_existing_revision(&row.uuid, query.to).await?;
+
+        let patch = patch::diff_manifests(&from.manifest, &to.manifest)?;
+
+        Ok(DiffResourceRevisionsOutput { patch })
+    }
+
+    pub async fn rollback_resource(
+        &mut self,
+        cmd: RollbackResourceCmd,
+    ) -> Result<PatchResourceAck> {
+        let mut trail = AuditTrail::new("rollback_resource", &cmd.namespace, &cmd.auth);
+        let result = self.try_rollback_resource(cmd, &mut trail).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_rollback_resource(
+        &mut self,
+        cmd: RollbackResourceCmd,
+        trail: &mut AuditTrail,
+    ) -> Result<PatchResourceAck> {
+        info!("rolling back resource");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesWrite)?;
+
+        let row = self
+            .get_latest_resource(&cmd.namespace, &cmd.resource)
+            .await?;
+        trail.set_resource_uuid(&row.uuid);
+
+        if row.deleting {
+            bail!("resource is being deleted")
+        }
+
+        if cmd.revision == row.revision as Revision {
+            let err = anyhow!("resource is already at revision {}", cmd.revision);
+            return Err(InvalidArgument(err).into());
+        }
+
+        let target = self.get_existing_revision(&row.uuid, cmd.revision).await?;
+
+        // the old manifest goes out as a regular patch so that it replicates
+        // like any other change
+        self.submit_resource_manifest(row, target.manifest).await
+    }
+
+    pub async fn delete_resource(&mut self, cmd: DeleteResourceCmd) -> Result<DeleteResourceAck> {
+        let mut trail = AuditTrail::new("delete_resource", &cmd.namespace, &cmd.auth);
+        let result = self.try_delete_resource(cmd, &mut trail).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_delete_resource(
+        &mut self,
+        cmd: DeleteResourceCmd,
+        trail: &mut AuditTrail,
+    ) -> Result<DeleteResourceAck> {
+        info!("deleting resource");
+
+        self.assert_existing_namespace(&cmd.namespace).await?;
+
+        let digest = cmd.command_digest();
+        let principal = self
+            .assert_valid_credentials(&cmd.namespace, cmd.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesWrite)?;
+
+        let row = self
+            .get_existing_resource(&cmd.namespace, &cmd.resource)
+            .await?;
+        trail.set_resource_uuid(&row.uuid);
+
+        if row.deleting {
+            bail!("resource is already being deleted")
+        }
+
+        let resource_uuid = row.uuid.clone();
+
+        let metadata = resource_metadata(
+            &self.fabric_state,
+            cmd.namespace,
+            row.kind,
+            row.name,
+            row.uuid,
+        )
+        .await?;
+
+        let event_receipt = self
+            .event_dispatch
+            .submit_event(ResourceDeletedV1 {
+                metadata,
+                finalizers: self.config.resource_finalizers.clone(),
+            })
+            .await?;
+
+        Ok(DeleteResourceAck {
+            event_receipt,
+            resource_uuid,
+        })
+    }
+
+    async fn remove_resource_if_finalized(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        uuid: &ResourceUuid,
+    ) -> Result<()> {
+        if self
+            .fabric_state
+            .delete_resource_if_finalized(tx, uuid)
+            .await?
+        {
+            info!("resource removed");
+        }
+
+        Ok(())
+    }
+
+    async fn on_resource_deleted(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ResourceDeletedV1,
+    ) -> Result<()> {
+        info!("resource deleted");
+
+        self.fabric_state
+            .mark_resource_deleting(
+                tx,
+                &evt.metadata.namespace,
+                &evt.metadata.uuid,
+                &evt.finalizers,
+            )
+            .await?;
+
+        self.remove_resource_if_finalized(tx, &evt.metadata.uuid)
+            .await
+    }
+
+    async fn on_resource_finalized(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ResourceFinalizedV1,
+    ) -> Result<()> {
+        info!(finalizer = evt.finalizer, "resource finalized");
+
+        self.fabric_state
+            .remove_resource_finalizer(tx, &evt.resource, &evt.finalizer)
+            .await?;
+
+        self.remove_resource_if_finalized(tx, &evt.resource).await
+    }
+
+    pub async fn read_resource(&self, query: ReadResourceQuery) -> Result<ReadResourceOutput> {
+        let mut trail = AuditTrail::new("read_resource", &query.namespace_name, &query.auth);
+        let result = self.try_read_resource(query).await;
+
+        if let Ok(out) = &result {
+            trail.set_resource_uuid(&out.metadata.uuid);
+        }
+
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_read_resource(&self, query: ReadResourceQuery) -> Result<ReadResourceOutput> {
+        self.assert_existing_namespace(&query.namespace_name)
+            .await?;
+
+        let digest = query.command_digest();
+        let principal = self
+            .assert_valid_credentials(&query.namespace_name, query.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesRead)?;
+
+        let row = self
+            .get_existing_resource(&query.namespace_name, &query.resource)
+            .await?;
+
+        let metadata = resource_metadata(
+            &self.fabric_state,
+            row.namespace,
+            row.kind,
+            row.name,
+            row.uuid,
+        )
+        .await?;
+
+        Ok(ReadResourceOutput {
+            metadata,
+            spec: row.manifest,
+            status: row.status,
+            revision: row.revision as Revision,
+            state: ResourceState::from_deleting(row.deleting),
+        })
+    }
+
+    async fn on_resource_status_updated(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ResourceStatusUpdatedV1,
+    ) -> Result<()> {
+        info!("resource status updated");
+
+        self.fabric_state
+            .update_resource_status(tx, &evt.namespace, &evt.resource, &evt.status)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn watch_resources(&self, query: WatchResourcesQuery) -> Result<ResourceWatch> {
+        let trail = AuditTrail::new("watch_resources", &query.namespace_name, &query.auth);
+        let result = self.try_watch_resources(query).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_watch_resources(&self, query: WatchResourcesQuery) -> Result<ResourceWatch> {
+        self.assert_existing_namespace(&query.namespace_name)
+            .await?;
+
+        let digest = query.command_digest();
+        let principal = self
+            .assert_valid_credentials(&query.namespace_name, query.auth, &digest)
+            .await?;
+        principal.authorize(Scope::ResourcesRead)?;
+
+        let (pending, receiver) = self.event_dispatch.subscribe_from(&query.from).await?;
+
+        Ok(ResourceWatch {
+            namespace: query.namespace_name,
+            fabric_state: self.fabric_state.clone(),
+            pending,
+            receiver,
+        })
+    }
+
+    pub async fn read_balance(&self, query: ReadBalanceQuery) -> Result<ReadBalanceOutput> {
+        let trail = AuditTrail::new("read_balance", &query.namespace_name, &query.auth);
+        let result = self.try_read_balance(query).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_read_balance(&self, query: ReadBalanceQuery) -> Result<ReadBalanceOutput> {
+        self.assert_existing_namespace(&query.namespace_name)
+            .await?;
+
+        let digest = query.command_digest();
+        let principal = self
+            .assert_valid_credentials(&query.namespace_name, query.auth, &digest)
+            .await?;
+        principal.authorize(Scope::BillingRead)?;
+
+        let accounts = self
+            .fabric_state
+            .read_balance(&query.namespace_name)
+            .await?
+            .into_iter()
+            .map(|(a, b, c)| (a as u64, b as u64, c as u64))
+            .collect();
+
+        Ok(ReadBalanceOutput { accounts })
+    }
+
+    pub async fn read_audit_log(&self, query: ReadAuditLogQuery) -> Result<ReadAuditLogOutput> {
+        let trail = AuditTrail::new("read_audit_log", &query.namespace_name, &query.auth);
+        let result = self.try_read_audit_log(query).await;
+        self.audit(trail, &result).await;
+        result
+    }
+
+    async fn try_read_audit_log(&self, query: ReadAuditLogQuery) -> Result<ReadAuditLogOutput> {
+        self.assert_existing_namespace(&query.namespace_name)
+            .await?;
+
+        let digest = query.command_digest();
+        let principal = self
+            .assert_valid_credentials(&query.namespace_name, query.auth, &digest)
+            .await?;
+        principal.authorize(Scope::AuditRead)?;
+
+        let page_size = query.page_size.clamp(1, MAX_AUDIT_PAGE_SIZE);
+
+        let rows = self
+            .fabric_state
+            .list_audit_records(&query.namespace_name, query.before, page_size as i64)
+            .await?;
+
+        let next_cursor = match rows.last() {
+            Some(x) if rows.len() == page_size as usize => Some(x.id),
+            _ => None,
+        };
+
+        let records = rows.into_iter().map(|x| x.record).collect();
+
+        Ok(ReadAuditLogOutput {
+            records,
+            next_cursor,
+        })
+    }
+
+    async fn on_resource_usage(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: ResourceUsageV1,
+    ) -> Result<()> {
+        info!("resource usage");
+
+        self.fabric_state
+            .insert_accounting(
+                tx,
+                evt.epoch as i64,
+                &evt.entry,
+                &evt.cluster,
+                &evt.namespace,
+                Some(&evt.resource),
+                vec![
+                    AccountDelta {
+                        account: 1,
+                        debit: Some(evt.units as i64),
+                        credit: None,
+                    },
+                    AccountDelta {
+                        account: 2,
+                        debit: None,
+                        credit: Some(evt.units as i64),
+                    },
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn on_usage_payment(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        evt: UsagePaymentV1,
+    ) -> Result<()> {
+        info!("usage payment");
+
+        self.fabric_state
+            .insert_accounting(
+                tx,
+                evt.epoch as i64,
+                &evt.entry,
+                &evt.cluster,
+                &evt.namespace,
+                None,
+                vec![
+                    AccountDelta {
+                        account: 2,
+                        debit: Some(evt.units as i64),
+                        credit: None,
+                    },
+                    AccountDelta {
+                        account: 3,
+                        debit: None,
+                        credit: Some(evt.units as i64),
+                    },
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Handles an event coming from the dispatch and moves the checkpoint
+    /// past it. An event that can't be projected is set aside in the failed
+    /// events rather than holding back the ones after it, so this only fails
+    /// when the projections can't be written at all.
+    pub async fn apply(&mut self, event: EventWrapper) -> Result<()> {
+        let EventWrapper(event, receipt, sequence) = event;
+
+        let Err(error) = self.project(event, receipt.clone(), Some(sequence)).await else {
+            return Ok(());
+        };
+
+        error!(
+            sequence,
+            ?error,
+            "setting aside event that can't be projected"
+        );
+
+        let mut tx = self.fabric_state.begin().await?;
+
+        self.fabric_state
+            .mark_event_applied(&mut tx, &receipt)
+            .await?;
+        self.fabric_state
+            .insert_failed_event(&mut tx, &receipt, sequence as i64, &format!("{error:#}"))
+            .await?;
+        self.fabric_state
+            .set_checkpoint(&mut tx, sequence as i64)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Projects an event within a single transaction that also records its
+    /// receipt. Events whose receipt is already recorded are skipped, which
+    /// makes redeliveries and replays no-ops.
+    async fn project(
+        &mut self,
+        event: Event,
+        receipt: EventReceipt,
+        sequence: Option<EventSequence>,
+    ) -> Result<()> {
+        let mut tx = self.fabric_state.begin().await?;
+
+        // dropping the transaction on error rolls it back
+        self.try_project(&mut tx, event, receipt, sequence).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn try_project(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        event: Event,
+        receipt: EventReceipt,
+        sequence: Option<EventSequence>,
+    ) -> Result<()> {
+        if self.fabric_state.mark_event_applied(tx, &receipt).await? {
+            self.on_event(tx, event, receipt).await?;
+        } else {
+            warn!(?sequence, "skipping event that was already applied");
+        }
+
+        if let Some(sequence) = sequence {
+            self.fabric_state
+                .set_checkpoint(tx, sequence as i64)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the logged events after the checkpoint, returning the sequence
+    /// that the projections are caught up with
+    pub async fn catch_up(&mut self) -> Result<EventSequence> {
+        loop {
+            let checkpoint = self.fabric_state.get_checkpoint().await? as u64;
+
+            let events = self
+                .event_dispatch
+                .read_log(checkpoint, REPLAY_BATCH_SIZE)
+                .await?;
+
+            if events.is_empty() {
+                return Ok(checkpoint);
+            }
+
+            for event in events {
+                self.apply(event).await?;
+            }
+        }
+    }
+
+    /// Drops the projections and rebuilds them from the start of the log.
+    /// Refuses to when the log doesn't account for everything in the
+    /// projections, such as data that predates it, since replaying couldn't
+    /// bring that back.
+    pub async fn rebuild_projections(&mut self) -> Result<EventSequence> {
+        self.assert_log_covers_projections().await?;
+
+        warn!("dropping projections to rebuild them from the event log");
+
+        self.fabric_state.reset_projections().await?;
+        self.catch_up().await
+    }
+
+    async fn assert_log_covers_projections(&self) -> Result<()> {
+        let mut namespaces = HashSet::new();
+        let mut api_keys = HashSet::new();
+        let mut members = HashSet::new();
+        let mut resources = HashSet::new();
+        let mut entries = HashSet::new();
+
+        let mut expected = 1;
+
+        loop {
+            let events = self
+                .event_dispatch
+                .read_log(expected - 1, REPLAY_BATCH_SIZE)
+                .await?;
+
+            if events.is_empty() {
+                break;
+            }
+
+            for EventWrapper(event, _, sequence) in events {
+                if sequence != expected {
+                    bail!("event {expected} is missing from the log");
+                }
+
+                expected += 1;
+
+                match event {
+                    Event::NamespaceMintedV1(x) => {
+                        namespaces.insert(x.name);
+                    }
+                    Event::ApiKeyRegisteredV1(x) => {
+                        api_keys.insert((x.namespace, Some(x.key_id)));
+                    }
+                    Event::MemberAddedV1(x) => {
+                        members.insert((x.namespace, x.public_key));
+                    }
+                    Event::ResourceCreatedV2(x) => {
+                        resources.insert(x.metadata.uuid);
+                    }
+                    Event::ResourceUsageV1(x) => {
+                        entries.insert((Some(x.cluster), Some(x.entry)));
+                    }
+                    Event::UsagePaymentV1(x) => {
+                        entries.insert((Some(x.cluster), Some(x.entry)));
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let missing = |found: bool, what: &str| match found {
+            true => Ok(()),
+            false => Err(anyhow!("the event log doesn't account for every {what}")),
+        };
+
+        let projected = self.fabric_state.list_namespace_names().await?;
+        missing(
+            projected.iter().all(|x| namespaces.contains(x)),
+            "namespace",
+        )?;
+
+        let projected = self.fabric_state.list_api_key_ids().await?;
+        missing(projected.iter().all(|x| api_keys.contains(x)), "api key")?;
+
+        let projected = self.fabric_state.list_member_keys().await?;
+        missing(projected.iter().all(|x| members.contains(x)), "member")?;
+
+        let projected = self.fabric_state.list_resource_uuids().await?;
+        missing(projected.iter().all(|x| resources.contains(x)), "resource")?;
+
+        let projected = self.fabric_state.list_accounting_entries().await?;
+        missing(
+            projected.iter().all(|x| entries.contains(x)),
+            "accounting entry",
+        )?;
+
+        Ok(())
+    }
+
+    /// Projects an event that doesn't come from the log, skipping it if its
+    /// receipt was already applied
+    pub async fn handle(&mut self, event: Event, receipt: EventReceipt) -> Result<()> {
+        self.project(event, receipt, None).await
+    }
+
+    async fn on_event(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        event: Event,
+        receipt: EventReceipt,
+    ) -> Result<()> {
+        info!(?event, "event recevied");
+
+        match event {
+            Event::NamespaceMintedV1(evt) => self.on_namespace_minted(tx, evt).await,
+            Event::ApiKeyRegisteredV1(evt) => self.on_apikey_registered(tx, evt).await,
+            Event::ApiKeyRevokedV1(evt) => self.on_apikey_revoked(tx, evt).await,
+            Event::ApiKeyRotatedV1(evt) => self.on_apikey_rotated(tx, evt).await,
+            Event::MemberAddedV1(evt) => self.on_member_added(tx, evt).await,
+            Event::MemberRoleChangedV1(evt) => self.on_member_role_changed(tx, evt).await,
+            Event::MemberRemovedV1(evt) => self.on_member_removed(tx, evt).await,
+            Event::ResourceCreatedV2(evt) => self.on_resource_created(tx, evt, receipt).await,
+            Event::ResourcePatchedV2(evt) => self.on_resource_patched(tx, evt, receipt).await,
+            Event::ResourceStatusUpdatedV1(evt) => self.on_resource_status_updated(tx, evt).await,
+            Event::ResourceDeletedV1(evt) => self.on_resource_deleted(tx, evt).await,
+            Event::ResourceFinalizedV1(evt) => self.on_resource_finalized(tx, evt).await,
+            Event::ResourceUsageV1(evt) => self.on_resource_usage(tx, evt).await,
+            Event::UsagePaymentV1(evt) => self.on_usage_payment(tx, evt).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tokio::sync::broadcast::Receiver;
+    use tokio::sync::Mutex;
+
+    use crate::driven::event_dispatch::{new_receipt, EventWrapper};
+
+    use super::*;
+
+    fn sign(key: &SigningKey, ns: &str, cmd: &HashDigest) -> (SignatureValue, AuthTimestamp) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let payload = auth::signature_payload(ns, cmd, timestamp);
+        let signature = hex::encode(key.sign(&payload).to_bytes());
+
+        (signature, timestamp)
+    }
+
+    fn owner_signature(key: &SigningKey, ns: &str, cmd: &HashDigest) -> Credential {
+        let (signature, timestamp) = sign(key, ns, cmd);
+        Credential::OwnerSignatureV1(signature, timestamp)
+    }
+
+    fn member_signature(key: &SigningKey, ns: &str, cmd: &HashDigest) -> Credential {
+        let (signature, timestamp) = sign(key, ns, cmd);
+        let public_key = key.verifying_key().to_bytes().to_vec();
+        Credential::MemberSignatureV1(public_key, signature, timestamp)
+    }
+
+    /// Domain without kinds or finalizers, for tests that only deal with
+    /// events
+    async fn bare_domain(event_dispatch: EventDispatch) -> Domain {
+        Domain {
+            config: Config {
+                cluster: b"123".into(),
+                apikey_hashing: HashParams::default(),
+                apikey_rotation_grace: 3600,
+                resource_finalizers: vec![],
+                kinds: KindRegistry::new(vec![]).unwrap(),
+            },
+            fabric_state: FabricState::ephemeral().await.unwrap(),
+            event_dispatch,
+            pending_revisions: Default::default(),
+        }
+    }
+
+    /// Projects the events submitted since the last call, standing in for the
+    /// fabric monitor
+    async fn project_pending(domain: &mut Domain, subscription: &mut Receiver<EventWrapper>) {
+        while let Ok(event) = subscription.try_recv() {
+            domain.apply(event).await.unwrap();
+        }
+    }
+
+    /// Submits the minting of `ns1`, returning its root key
+    async fn mint_namespace(domain: &mut Domain) -> SigningKey {
+        let root_key = SigningKey::from_bytes(&[7u8; 32]);
+
+        domain
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: root_key.verifying_key().to_bytes().to_vec(),
+            })
+            .await
+            .unwrap();
+
+        root_key
+    }
+
+    fn worker_kinds() -> KindRegistry {
+        KindRegistry::new(vec![KindDefinition {
+            kind: "workers.demeter.run/v1alpha1".into(),
+            schema: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "image": {"type": "string"},
+                    "replicas": {"type": "integer", "minimum": 0},
+                },
+                "additionalProperties": false,
+            }),
+        }])
+        .unwrap()
+    }
+
+    fn worker(name: &str) -> ResourceRef {
+        ResourceRef::Name("workers.demeter.run/v1alpha1".into(), name.into())
+    }
+
+    fn worker_created(name: &str, uuid: &[u8]) -> ResourceCreatedV2 {
+        ResourceCreatedV2 {
+            metadata: ResourceMetadataV1 {
+                namespace: "ns1".into(),
+                kind: "workers.demeter.run/v1alpha1".into(),
+                name: name.into(),
+                uuid: uuid.to_vec(),
+                labels: Default::default(),
+                annotations: Default::default(),
+            },
+            manifest: b"{}".to_vec(),
+            timestamp: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn happy_path() {
+        tracing_subscriber::fmt::init();
+
+        let fabric_state = FabricState::ephemeral().await.unwrap();
+        let event_dispatch = EventDispatch::ephemeral(100);
+
+        let mut domain = Domain {
+            config: Config {
+                cluster: b"123".into(),
+                apikey_hashing: HashParams::default(),
+                apikey_rotation_grace: 3600,
+                resource_finalizers: vec![],
+                kinds: worker_kinds(),
+            },
+            fabric_state,
+            event_dispatch,
+            pending_revisions: Default::default(),
+        };
+
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        let domain = Arc::new(Mutex::new(domain));
+
+        let domain2 = domain.clone();
+        let watcher = tokio::spawn(async move {
+            while let Ok(EventWrapper(evt, receipt, _)) = subscription.recv().await {
+                domain2.lock().await.handle(evt, receipt).await.unwrap();
+            }
+        });
+
+        let root_key = SigningKey::from_bytes(&[7u8; 32]);
+
+        // extrinsic event
+        domain
+            .lock()
+            .await
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: root_key.verifying_key().to_bytes().to_vec(),
+            })
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        let mut cmd = RegisterApiKeyCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            secret: b"mybadpassword".to_vec(),
+            scopes: Scope::ALL.to_vec(),
+            not_before: None,
+            not_after: None,
+        };
+
+        cmd.auth = owner_signature(&root_key, "ns1", &cmd.command_digest());
+
+        let key_ack = domain.lock().await.register_apikey(cmd).await.unwrap();
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        let res_ack = domain
+            .lock()
+            .await
+            .create_resource(CreateResourceCmd {
+                auth: Credential::ApiKeyV2(key_ack.key_id.clone(), b"mybadpassword".to_vec()),
+                namespace: "ns1".into(),
+                name: "res1".into(),
+                kind: "workers.demeter.run/v1alpha1".into(),
+                spec: br#"{"image":"abc"}"#.into(),
+                labels: Default::default(),
+                annotations: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        // extrinsic event
+        domain
+            .lock()
+            .await
+            .event_dispatch
+            .submit_event(ResourceUsageV1 {
+                entry: b"1".into(),
+                epoch: 123,
+                namespace: "ns1".into(),
+                resource: res_ack.resource_uuid,
+                cluster: b"cluster1".into(),
+                units: 500,
+            })
+            .await
+            .unwrap();
+
+        // extrinsic event
+        domain
+            .lock()
+            .await
+            .event_dispatch
+            .submit_event(UsagePaymentV1 {
+                entry: b"1".into(),
+                epoch: 123,
+                namespace: "ns1".into(),
+                cluster: b"cluster1".into(),
+                units: 400,
+            })
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        let balance = domain
+            .lock()
+            .await
+            .read_balance(ReadBalanceQuery {
+                auth: Credential::ApiKeyV2(key_ack.key_id, b"mybadpassword".to_vec()),
+                namespace_name: "ns1".into(),
+            })
+            .await
+            .unwrap();
+
+        dbg!(balance);
+
+        watcher.abort();
+    }
+
+    #[tokio::test]
+    async fn api_keys_cant_grant_more_than_they_hold() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        let root_key = SigningKey::from_bytes(&[7u8; 32]);
+
+        domain
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: root_key.verifying_key().to_bytes().to_vec(),
+            })
+            .await
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let mut cmd = RegisterApiKeyCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            secret: b"narrow".to_vec(),
+            scopes: vec![Scope::ApiKeysWrite],
+            not_before: None,
+            not_after: None,
+        };
+
+        cmd.auth = owner_signature(&root_key, "ns1", &cmd.command_digest());
+
+        let narrow = domain.register_apikey(cmd).await.unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let broader = domain
+            .register_apikey(RegisterApiKeyCmd {
+                auth: Credential::ApiKeyV2(narrow.key_id.clone(), b"narrow".to_vec()),
+                namespace: "ns1".into(),
+                secret: b"broader".to_vec(),
+                scopes: vec![Scope::ApiKeysWrite, Scope::BillingRead],
+                not_before: None,
+                not_after: None,
+            })
+            .await;
+
+        assert!(broader.is_err());
+
+        domain
+            .register_apikey(RegisterApiKeyCmd {
+                auth: Credential::ApiKeyV2(narrow.key_id, b"narrow".to_vec()),
+                namespace: "ns1".into(),
+                secret: b"same".to_vec(),
+                scopes: vec![Scope::ApiKeysWrite],
+                not_before: None,
+                not_after: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_owners_grant_the_owner_role() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        let root_key = SigningKey::from_bytes(&[7u8; 32]);
+        let admin_key = SigningKey::from_bytes(&[8u8; 32]);
+
+        domain
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: root_key.verifying_key().to_bytes().to_vec(),
+            })
+            .await
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let mut cmd = AddMemberCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            public_key: admin_key.verifying_key().to_bytes().to_vec(),
+            role: Role::Admin,
+        };
+
+        cmd.auth = owner_signature(&root_key, "ns1", &cmd.command_digest());
+        domain.add_member(cmd).await.unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let mut cmd = AddMemberCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            public_key: SigningKey::from_bytes(&[9u8; 32])
+                .verifying_key()
+                .to_bytes()
+                .to_vec(),
+            role: Role::Owner,
+        };
+
+        cmd.auth = member_signature(&admin_key, "ns1", &cmd.command_digest());
+        let denied = domain.add_member(cmd).await.unwrap_err();
+
+        assert!(denied.is::<PermissionDenied>());
+    }
+
+    #[tokio::test]
+    async fn signatures_cant_be_replayed() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        let root_key = SigningKey::from_bytes(&[7u8; 32]);
+
+        domain
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: root_key.verifying_key().to_bytes().to_vec(),
+            })
+            .await
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let cmd = || RegisterApiKeyCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            secret: b"secret".to_vec(),
+            scopes: vec![Scope::ResourcesRead],
+            not_before: None,
+            not_after: None,
+        };
+
+        let auth = owner_signature(&root_key, "ns1", &cmd().command_digest());
+
+        domain
+            .register_apikey(RegisterApiKeyCmd {
+                auth: auth.clone(),
+                ..cmd()
+            })
+            .await
+            .unwrap();
+
+        let replayed = domain
+            .register_apikey(RegisterApiKeyCmd { auth, ..cmd() })
+            .await;
+        assert!(replayed.is_err_and(|x| x.is::<InvalidCredentials>()));
+    }
+
+    #[tokio::test]
+    async fn legacy_keys_rotate_themselves() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        domain
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: vec![],
+            })
+            .await
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        // as left behind by the migration that gave legacy keys an id
+        let hash_params = HashParams::default();
+        let salt = auth::random_salt();
+        let mut tx = domain.fabric_state.begin().await.unwrap();
+        domain
+            .fabric_state
+            .insert_api_key(
+                &mut tx,
+                "ns1",
+                &ApiKey {
+                    key_id: Some(b"legacy01".to_vec()),
+                    digest: auth::digest(b"oldsecret", &salt, &hash_params)
+                        .unwrap()
+                        .to_vec(),
+                    salt,
+                    hashing: (&hash_params).into(),
+                    validity: ApiKeyValidity::default(),
+                    scopes: None,
+                    revoked: false,
+                    legacy: true,
+                },
             )
-            .await?;
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
 
-        Ok(())
+        let balance = |auth| ReadBalanceQuery {
+            auth,
+            namespace_name: "ns1".into(),
+        };
+
+        domain
+            .read_balance(balance(Credential::ApiKeyV1(b"oldsecret".to_vec())))
+            .await
+            .unwrap();
+
+        let ack = domain
+            .rotate_apikey(RotateApiKeyCmd {
+                auth: Credential::ApiKeyV1(b"oldsecret".to_vec()),
+                namespace: "ns1".into(),
+                key_id: vec![],
+                secret: b"newsecret".to_vec(),
+                not_after: None,
+                grace_period: Some(0),
+            })
+            .await
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let old = domain
+            .read_balance(balance(Credential::ApiKeyV1(b"oldsecret".to_vec())))
+            .await;
+        assert!(old.unwrap_err().is::<InvalidCredentials>());
+
+        domain
+            .read_balance(balance(Credential::ApiKeyV2(
+                ack.key_id,
+                b"newsecret".to_vec(),
+            )))
+            .await
+            .unwrap();
     }
 
-    async fn on_usage_payment(&mut self, evt: UsagePaymentV1) -> Result<()> {
-        info!("usage payment");
+    #[tokio::test]
+    async fn legacy_keys_can_be_revoked() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
 
-        self.fabric_state
-            .insert_accounting(
-                evt.epoch as i64,
-                &evt.entry,
-                &evt.cluster,
-                &evt.namespace,
-                None,
-                vec![
-                    AccountDelta {
-                        account: 2,
-                        debit: Some(evt.units as i64),
-                        credit: None,
-                    },
-                    AccountDelta {
-                        account: 3,
-                        debit: None,
-                        credit: Some(evt.units as i64),
+        domain
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: vec![],
+            })
+            .await
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let hash_params = HashParams::default();
+        let mut tx = domain.fabric_state.begin().await.unwrap();
+
+        for (key_id, secret) in [(b"legacy01", b"secret1"), (b"legacy02", b"secret2")] {
+            let salt = auth::random_salt();
+            domain
+                .fabric_state
+                .insert_api_key(
+                    &mut tx,
+                    "ns1",
+                    &ApiKey {
+                        key_id: Some(key_id.to_vec()),
+                        digest: auth::digest(secret, &salt, &hash_params).unwrap().to_vec(),
+                        salt,
+                        hashing: (&hash_params).into(),
+                        validity: ApiKeyValidity::default(),
+                        scopes: None,
+                        revoked: false,
+                        legacy: true,
                     },
-                ],
-            )
-            .await?;
+                )
+                .await
+                .unwrap();
+        }
 
-        Ok(())
-    }
+        tx.commit().await.unwrap();
 
-    pub async fn handle(&mut self, event: Event) -> Result<()> {
-        info!(?event, "event recevied");
+        // by the id the migration assigned
+        let cmd = |auth, key_id: &[u8]| RevokeApiKeyCmd {
+            auth,
+            namespace: "ns1".into(),
+            key_id: key_id.to_vec(),
+        };
 
-        match event {
-            Event::NamespaceMintedV1(evt) => self.on_namespace_minted(evt).await,
-            Event::ApiKeyRegisteredV1(evt) => self.on_apikey_registered(evt).await,
-            Event::ResourceCreatedV1(evt) => self.on_resource_created(evt).await,
-            Event::ResourceUsageV1(evt) => self.on_resource_usage(evt).await,
-            Event::UsagePaymentV1(evt) => self.on_usage_payment(evt).await,
+        domain
+            .revoke_apikey(cmd(Credential::ApiKeyV1(b"secret1".to_vec()), b"legacy02"))
+            .await
+            .unwrap();
+
+        // by the holder of the token, which doesn't carry the id
+        domain
+            .revoke_apikey(cmd(Credential::ApiKeyV1(b"secret1".to_vec()), b""))
+            .await
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        for secret in [b"secret1", b"secret2"] {
+            let denied = domain
+                .read_balance(ReadBalanceQuery {
+                    auth: Credential::ApiKeyV1(secret.to_vec()),
+                    namespace_name: "ns1".into(),
+                })
+                .await;
+
+            assert!(denied.unwrap_err().is::<InvalidCredentials>());
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{sync::Arc, time::Duration};
-    use tokio::sync::Mutex;
+    #[tokio::test]
+    async fn redelivered_events_are_noops() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
 
-    use crate::driven::event_dispatch::EventWrapper;
+        let minted: Event = NamespaceMintedV1 {
+            name: "ns1".into(),
+            root_public_key: vec![],
+        }
+        .into();
 
-    use super::*;
+        let receipt = new_receipt();
+        domain
+            .handle(minted.clone(), receipt.clone())
+            .await
+            .unwrap();
+        domain.handle(minted.clone(), receipt).await.unwrap();
+
+        let usage: Event = ResourceUsageV1 {
+            entry: b"1".into(),
+            epoch: 1,
+            namespace: "ns1".into(),
+            resource: b"res1".into(),
+            cluster: b"cluster1".into(),
+            units: 100,
+        }
+        .into();
+
+        let receipt = new_receipt();
+        domain.handle(usage.clone(), receipt.clone()).await.unwrap();
+        domain.handle(usage, receipt).await.unwrap();
+
+        let balance = domain.fabric_state.read_balance("ns1").await.unwrap();
+        assert_eq!(balance, vec![(1, 100, 0), (2, 0, 100)]);
+
+        // a failed event leaves nothing behind, its receipt included, so it
+        // can be delivered again
+        let receipt = new_receipt();
+        assert!(domain.handle(minted, receipt.clone()).await.is_err());
+        let mut tx = domain.fabric_state.begin().await.unwrap();
+        assert!(domain
+            .fabric_state
+            .mark_event_applied(&mut tx, &receipt)
+            .await
+            .unwrap());
+    }
 
     #[tokio::test]
-    async fn happy_path() {
-        tracing_subscriber::fmt::init();
+    async fn redelivered_usage_is_billed_once() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
 
-        let fabric_state = FabricState::ephemeral().await.unwrap();
-        let event_dispatch = EventDispatch::ephemeral(100);
+        domain
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns1".into(),
+                root_public_key: vec![],
+            })
+            .await
+            .unwrap();
 
-        let mut domain = Domain {
-            config: Config {
-                cluster: b"123".into(),
-            },
-            fabric_state,
-            event_dispatch,
+        let usage = ResourceUsageV1 {
+            entry: b"1".into(),
+            epoch: 1,
+            namespace: "ns1".into(),
+            resource: b"res1".into(),
+            cluster: b"cluster1".into(),
+            units: 100,
         };
 
-        let mut subscription = domain.event_dispatch.subscribe();
+        let receipt = new_receipt();
 
-        let domain = Arc::new(Mutex::new(domain));
+        for expected in [true, false] {
+            let dispatched = domain
+                .event_dispatch
+                .ingest_event(usage.clone(), receipt.clone())
+                .await
+                .unwrap();
 
-        let domain2 = domain.clone();
-        let watcher = tokio::spawn(async move {
-            while let Ok(EventWrapper(evt, _)) = subscription.recv().await {
-                domain2.lock().await.handle(evt).await.unwrap();
-            }
-        });
+            assert_eq!(dispatched, expected);
+        }
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let balance = domain.fabric_state.read_balance("ns1").await.unwrap();
+        assert_eq!(balance, vec![(1, 100, 0), (2, 0, 100)]);
+    }
+
+    #[tokio::test]
+    async fn unprojectable_events_are_set_aside() {
+        let path = std::env::temp_dir().join(format!("events-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let mut domain = bare_domain(EventDispatch::persistent(&path, 10).await.unwrap()).await;
+
+        // the second one can't be projected, the namespace is already taken
+        for name in ["ns1", "ns1", "ns2"] {
+            domain
+                .event_dispatch
+                .submit_event(NamespaceMintedV1 {
+                    name: name.into(),
+                    root_public_key: vec![],
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(domain.catch_up().await.unwrap(), 3);
+        assert!(domain.fabric_state.namespace_exists("ns2").await.unwrap());
+
+        let failed = domain.fabric_state.list_failed_events().await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].sequence, 2);
+        assert!(failed[0].error.contains("namespace isn't available"));
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_rebuilds_projections() {
+        let path = std::env::temp_dir().join(format!("events-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let mut domain = bare_domain(EventDispatch::persistent(&path, 10).await.unwrap()).await;
+
+        for name in ["ns1", "ns2"] {
+            domain
+                .event_dispatch
+                .submit_event(NamespaceMintedV1 {
+                    name: name.into(),
+                    root_public_key: vec![],
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(domain.catch_up().await.unwrap(), 2);
+        assert!(domain.fabric_state.namespace_exists("ns2").await.unwrap());
+
+        // applying an event twice would fail the namespace invariant
+        let (pending, _) = domain
+            .event_dispatch
+            .subscribe_from(&ResumeFrom::Sequence(0))
+            .await
+            .unwrap();
+        for event in pending {
+            domain.apply(event).await.unwrap();
+        }
 
-        // extrinsic event
         domain
-            .lock()
+            .event_dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns3".into(),
+                root_public_key: vec![],
+            })
             .await
+            .unwrap();
+
+        assert_eq!(domain.catch_up().await.unwrap(), 3);
+
+        assert_eq!(domain.rebuild_projections().await.unwrap(), 3);
+        for name in ["ns1", "ns2", "ns3"] {
+            assert!(domain.fabric_state.namespace_exists(name).await.unwrap());
+        }
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn rebuilds_keep_data_the_log_doesnt_cover() {
+        let path = std::env::temp_dir().join(format!("events-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let mut domain = bare_domain(EventDispatch::persistent(&path, 10).await.unwrap()).await;
+
+        domain
             .event_dispatch
             .submit_event(NamespaceMintedV1 {
                 name: "ns1".into(),
-                root_public_key: "123".into(),
+                root_public_key: vec![],
             })
+            .await
             .unwrap();
 
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        domain.catch_up().await.unwrap();
 
+        // as seeded before the event log was turned on
+        let mut tx = domain.fabric_state.begin().await.unwrap();
         domain
-            .lock()
-            .await
-            .register_apikey(RegisterApiKeyCmd {
-                auth: Credential::OwnerSignatureV1("123".into(), 1234),
-                namespace: "ns1".into(),
-                secret: b"mybadpassword".to_vec(),
-            })
+            .fabric_state
+            .insert_namespace(&mut tx, "ns0", &[])
             .await
             .unwrap();
+        tx.commit().await.unwrap();
 
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(domain.rebuild_projections().await.is_err());
+        assert!(domain.fabric_state.namespace_exists("ns0").await.unwrap());
+        assert!(domain.fabric_state.namespace_exists("ns1").await.unwrap());
 
-        let res_ack = domain
-            .lock()
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_changes_are_audited_with_their_resource() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        let root_key = mint_namespace(&mut domain).await;
+
+        domain
+            .event_dispatch
+            .submit_event(worker_created("res1", b"uuid1"))
             .await
-            .create_resource(CreateResourceCmd {
-                auth: Credential::ApiKeyV1(b"mybadpassword".to_vec()),
-                namespace: "ns1".into(),
-                name: "res1".into(),
-                kind: "workers.demeter.run/v1alpha1".into(),
-                spec: b"abc".into(),
-            })
+            .unwrap();
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        // the kind isn't registered, so the patch fails once resolved
+        let mut cmd = PatchResourceCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            resource: worker("res1"),
+            patch_kind: PatchKind::Merge,
+            patch: br#"{"image":"a"}"#.to_vec(),
+        };
+
+        cmd.auth = owner_signature(&root_key, "ns1", &cmd.command_digest());
+        assert!(domain.patch_resource(cmd).await.is_err());
+
+        let mut cmd = RollbackResourceCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            resource: worker("res1"),
+            revision: 1,
+        };
+
+        cmd.auth = owner_signature(&root_key, "ns1", &cmd.command_digest());
+        assert!(domain.rollback_resource(cmd).await.is_err());
+
+        let audited = domain
+            .fabric_state
+            .list_audit_records("ns1", None, 2)
             .await
             .unwrap();
 
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        for row in audited {
+            assert_eq!(row.record.outcome, "failure");
+            assert_eq!(
+                row.record.resource_uuid.as_deref(),
+                Some(b"uuid1".as_slice())
+            );
+        }
+    }
 
-        // extrinsic event
-        domain
-            .lock()
+    #[tokio::test]
+    async fn duplicate_creates_are_rejected_by_the_projection() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        mint_namespace(&mut domain).await;
+
+        // both got past the command check before either was projected
+        for uuid in [b"first", b"later"] {
+            domain
+                .event_dispatch
+                .submit_event(worker_created("res1", uuid))
+                .await
+                .unwrap();
+        }
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let state = &domain.fabric_state;
+        let resource = state
+            .get_resource_by_name("ns1", "workers.demeter.run/v1alpha1", "res1")
             .await
-            .event_dispatch
-            .submit_event(ResourceUsageV1 {
-                entry: b"1".into(),
-                epoch: 123,
-                namespace: "ns1".into(),
-                resource: res_ack.resource_uuid,
-                cluster: b"cluster1".into(),
-                units: 500,
-            })
+            .unwrap()
             .unwrap();
 
-        // extrinsic event
+        assert_eq!(resource.uuid, b"first");
+        assert!(state.resource_was_rejected(b"later").await.unwrap());
+        assert!(!state.resource_was_rejected(b"first").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn patches_build_on_unprojected_revisions() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        domain.config.kinds = worker_kinds();
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        let root_key = mint_namespace(&mut domain).await;
+
         domain
-            .lock()
-            .await
             .event_dispatch
-            .submit_event(UsagePaymentV1 {
-                entry: b"1".into(),
-                epoch: 123,
-                namespace: "ns1".into(),
-                cluster: b"cluster1".into(),
-                units: 400,
+            .submit_event(ResourceCreatedV2 {
+                manifest: br#"{"replicas":1,"image":"a"}"#.to_vec(),
+                ..worker_created("res1", b"uuid1")
             })
+            .await
             .unwrap();
 
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        project_pending(&mut domain, &mut subscription).await;
 
-        let balance = domain
-            .lock()
+        let mut patches = vec![];
+
+        for patch in [br#"{"replicas":2}"#.as_slice(), br#"{"image":"b"}"#] {
+            let mut cmd = PatchResourceCmd {
+                auth: Credential::ApiKeyV1(vec![]),
+                namespace: "ns1".into(),
+                resource: worker("res1"),
+                patch_kind: PatchKind::Merge,
+                patch: patch.to_vec(),
+            };
+
+            cmd.auth = owner_signature(&root_key, "ns1", &cmd.command_digest());
+            patches.push(domain.patch_resource(cmd).await.unwrap().revision);
+        }
+
+        let mut cmd = RollbackResourceCmd {
+            auth: Credential::ApiKeyV1(vec![]),
+            namespace: "ns1".into(),
+            resource: worker("res1"),
+            revision: 1,
+        };
+
+        cmd.auth = owner_signature(&root_key, "ns1", &cmd.command_digest());
+        patches.push(domain.rollback_resource(cmd).await.unwrap().revision);
+
+        // nothing got projected in between
+        assert_eq!(patches, vec![2, 3, 4]);
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let revisions = domain
+            .fabric_state
+            .list_resource_revisions(b"uuid1")
             .await
-            .read_balance(ReadBalanceQuery {
-                auth: Credential::ApiKeyV1(b"mybadpassword".to_vec()),
+            .unwrap();
+
+        let manifests: Vec<_> = revisions.iter().map(|x| x.manifest.as_slice()).collect();
+        assert_eq!(
+            manifests,
+            vec![
+                br#"{"replicas":1,"image":"a"}"#.as_slice(),
+                br#"{"image":"a","replicas":2}"#,
+                br#"{"image":"b","replicas":2}"#,
+                br#"{"replicas":1,"image":"a"}"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn watches_resume_after_a_receipt() {
+        let mut domain = bare_domain(EventDispatch::ephemeral(10)).await;
+        let mut subscription = domain.event_dispatch.subscribe();
+
+        let root_key = mint_namespace(&mut domain).await;
+
+        let mut receipts = vec![];
+
+        for name in ["res1", "res2"] {
+            let receipt = domain
+                .event_dispatch
+                .submit_event(worker_created(name, name.as_bytes()))
+                .await
+                .unwrap();
+
+            receipts.push(receipt);
+        }
+
+        project_pending(&mut domain, &mut subscription).await;
+
+        let watch = |from: ResumeFrom| {
+            let mut query = WatchResourcesQuery {
+                auth: Credential::ApiKeyV1(vec![]),
                 namespace_name: "ns1".into(),
-            })
+                from,
+            };
+
+            query.auth = owner_signature(&root_key, "ns1", &query.command_digest());
+            query
+        };
+
+        let mut resumed = domain
+            .watch_resources(watch(ResumeFrom::Receipt(receipts[0].clone())))
             .await
             .unwrap();
 
-        dbg!(balance);
+        let change = resumed.next().await.unwrap().unwrap();
+        assert_eq!(change.event_receipt, receipts[1]);
+        assert_eq!(change.kind, WatchEventKind::Added);
 
-        watcher.abort();
+        // sequences are exclusive, so 0 replays the log from its start
+        let mut replayed = domain
+            .watch_resources(watch(ResumeFrom::Sequence(0)))
+            .await
+            .unwrap();
+
+        let change = replayed.next().await.unwrap().unwrap();
+        assert_eq!(change.event_receipt, receipts[0]);
     }
 }
@@ -0,0 +1,354 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+/// Formats accepted to patch a resource manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchKind {
+    /// JSON merge patch, as in RFC 7396
+    Merge,
+    /// JSON patch, as in RFC 6902
+    Json,
+}
+
+impl PatchKind {
+    pub const ALL: [PatchKind; 2] = [PatchKind::Merge, PatchKind::Json];
+
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            PatchKind::Merge => "application/merge-patch+json",
+            PatchKind::Json => "application/json-patch+json",
+        }
+    }
+}
+
+impl FromStr for PatchKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        PatchKind::ALL
+            .into_iter()
+            .find(|x| x.media_type() == s)
+            .ok_or_else(|| anyhow!("unknown patch type {s}"))
+    }
+}
+
+/// Applies a patch to a json manifest and returns the patched manifest
+pub fn apply_patch(manifest: &[u8], kind: PatchKind, patch: &[u8]) -> Result<Vec<u8>> {
+    let mut doc: Value = serde_json::from_slice(manifest).context("manifest isn't valid json")?;
+    let patch: Value = serde_json::from_slice(patch).context("patch isn't valid json")?;
+
+    match kind {
+        PatchKind::Merge => merge_patch(&mut doc, &patch),
+        PatchKind::Json => json_patch(&mut doc, &patch)?,
+    };
+
+    Ok(serde_json::to_vec(&doc)?)
+}
+
+/// Computes the json patch that turns one json manifest into another
+pub fn diff_manifests(from: &[u8], to: &[u8]) -> Result<Vec<u8>> {
+    let from: Value = serde_json::from_slice(from).context("manifest isn't valid json")?;
+    let to: Value = serde_json::from_slice(to).context("manifest isn't valid json")?;
+
+    let mut ops = vec![];
+    diff(&from, &to, "", &mut ops);
+
+    Ok(serde_json::to_vec(&ops)?)
+}
+
+fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+fn diff(from: &Value, to: &Value, path: &str, ops: &mut Vec<Value>) {
+    if from == to {
+        return;
+    }
+
+    // objects are diffed key by key, anything else is replaced as a whole
+    let (Value::Object(from), Value::Object(to)) = (from, to) else {
+        ops.push(json!({"op": "replace", "path": path, "value": to}));
+        return;
+    };
+
+    for (key, x) in from {
+        let path = format!("{path}/{}", escape_token(key));
+
+        match to.get(key) {
+            Some(y) => diff(x, y, &path, ops),
+            None => ops.push(json!({"op": "remove", "path": path})),
+        }
+    }
+
+    for (key, y) in to.iter().filter(|(key, _)| !from.contains_key(*key)) {
+        let path = format!("{path}/{}", escape_token(key));
+        ops.push(json!({"op": "add", "path": path, "value": y}));
+    }
+}
+
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+
+    let target = target.as_object_mut().unwrap();
+
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+fn json_patch(doc: &mut Value, patch: &Value) -> Result<()> {
+    let ops = patch
+        .as_array()
+        .ok_or_else(|| anyhow!("json patch must be an array of operations"))?;
+
+    // operations are all or nothing, so they're applied over a copy
+    let mut patched = doc.clone();
+
+    for op in ops {
+        apply_operation(&mut patched, op)?;
+    }
+
+    *doc = patched;
+
+    Ok(())
+}
+
+fn str_field<'a>(op: &'a Value, field: &str) -> Result<&'a str> {
+    op.get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("patch operation is missing {field}"))
+}
+
+fn value_field(op: &Value) -> Result<Value> {
+    op.get("value")
+        .cloned()
+        .ok_or_else(|| anyhow!("patch operation is missing value"))
+}
+
+fn apply_operation(doc: &mut Value, op: &Value) -> Result<()> {
+    let path = str_field(op, "path")?;
+
+    match str_field(op, "op")? {
+        "add" => add(doc, path, value_field(op)?),
+        "remove" => remove(doc, path).map(|_| ()),
+        "replace" => {
+            let target = doc
+                .pointer_mut(path)
+                .ok_or_else(|| anyhow!("path {path} doesn't exist"))?;
+
+            *target = value_field(op)?;
+            Ok(())
+        }
+        "move" => {
+            let from = str_field(op, "from")?;
+
+            if path.starts_with(&format!("{from}/")) {
+                bail!("can't move {from} into one of its children")
+            }
+
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        "copy" => {
+            let from = str_field(op, "from")?;
+
+            let value = doc
+                .pointer(from)
+                .cloned()
+                .ok_or_else(|| anyhow!("path {from} doesn't exist"))?;
+
+            add(doc, path, value)
+        }
+        "test" => {
+            if doc.pointer(path) != Some(&value_field(op)?) {
+                bail!("test failed for path {path}")
+            }
+
+            Ok(())
+        }
+        x => bail!("unknown patch operation {x}"),
+    }
+}
+
+/// Splits a json pointer into its parent pointer and its unescaped last token
+fn split_pointer(path: &str) -> Result<(&str, String)> {
+    let (parent, token) = path
+        .rsplit_once('/')
+        .ok_or_else(|| anyhow!("invalid json pointer {path}"))?;
+
+    Ok((parent, token.replace("~1", "/").replace("~0", "~")))
+}
+
+fn parse_index(token: &str, bound: usize) -> Result<usize> {
+    let index: usize = token
+        .parse()
+        .map_err(|_| anyhow!("invalid array index {token}"))?;
+
+    if index >= bound {
+        bail!("array index {index} out of bounds")
+    }
+
+    Ok(index)
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<()> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+
+    let (parent, token) = split_pointer(path)?;
+
+    let parent = doc
+        .pointer_mut(parent)
+        .ok_or_else(|| anyhow!("parent of {path} doesn't exist"))?;
+
+    match parent {
+        Value::Object(map) => {
+            map.insert(token, value);
+        }
+        Value::Array(items) => {
+            let index = match token.as_str() {
+                "-" => items.len(),
+                x => parse_index(x, items.len() + 1)?,
+            };
+
+            items.insert(index, value);
+        }
+        _ => bail!("parent of {path} isn't a container"),
+    };
+
+    Ok(())
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value> {
+    let (parent, token) = split_pointer(path)?;
+
+    let parent = doc
+        .pointer_mut(parent)
+        .ok_or_else(|| anyhow!("parent of {path} doesn't exist"))?;
+
+    match parent {
+        Value::Object(map) => map
+            .remove(&token)
+            .ok_or_else(|| anyhow!("path {path} doesn't exist")),
+        Value::Array(items) => {
+            let index = parse_index(&token, items.len())?;
+            Ok(items.remove(index))
+        }
+        _ => bail!("parent of {path} isn't a container"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(doc: Value, kind: PatchKind, patch: Value) -> Result<Value> {
+        let out = apply_patch(
+            &serde_json::to_vec(&doc).unwrap(),
+            kind,
+            &serde_json::to_vec(&patch).unwrap(),
+        )?;
+
+        Ok(serde_json::from_slice(&out).unwrap())
+    }
+
+    #[test]
+    fn merge_patch_follows_rfc7396() {
+        let out = patch(
+            json!({"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"}, "tags": ["example", "sample"]}),
+            PatchKind::Merge,
+            json!({"title": "Hello!", "author": {"familyName": null}, "phoneNumber": "+01-123-456-7890", "tags": ["example"]}),
+        )
+        .unwrap();
+
+        assert_eq!(
+            out,
+            json!({"title": "Hello!", "author": {"givenName": "John"}, "tags": ["example"], "phoneNumber": "+01-123-456-7890"})
+        );
+    }
+
+    #[test]
+    fn json_patch_follows_rfc6902() {
+        let out = patch(
+            json!({"replicas": 1, "ports": [80], "old": {"a": 1}}),
+            PatchKind::Json,
+            json!([
+                {"op": "test", "path": "/replicas", "value": 1},
+                {"op": "replace", "path": "/replicas", "value": 3},
+                {"op": "add", "path": "/ports/-", "value": 443},
+                {"op": "add", "path": "/ports/0", "value": 22},
+                {"op": "move", "from": "/old", "path": "/new"},
+                {"op": "copy", "from": "/new/a", "path": "/b"},
+                {"op": "remove", "path": "/ports/1"},
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            out,
+            json!({"replicas": 3, "ports": [22, 443], "new": {"a": 1}, "b": 1})
+        );
+    }
+
+    #[test]
+    fn json_patch_is_all_or_nothing() {
+        let failed = patch(
+            json!({"replicas": 1}),
+            PatchKind::Json,
+            json!([
+                {"op": "replace", "path": "/replicas", "value": 3},
+                {"op": "test", "path": "/replicas", "value": 1},
+            ]),
+        );
+
+        assert!(failed.is_err());
+
+        assert!(patch(json!({}), PatchKind::Json, json!({"op": "add"})).is_err());
+        assert!(patch(
+            json!({}),
+            PatchKind::Json,
+            json!([{"op": "remove", "path": "/x"}])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn diff_produces_an_applicable_json_patch() {
+        let from =
+            json!({"image": "nginx", "replicas": 1, "env": {"a": "1", "b/c": "2"}, "ports": [80]});
+        let to = json!({"image": "nginx", "replicas": 3, "env": {"a": "1"}, "ports": [80, 443], "tag": "x"});
+
+        let ops = diff_manifests(
+            &serde_json::to_vec(&from).unwrap(),
+            &serde_json::to_vec(&to).unwrap(),
+        )
+        .unwrap();
+
+        let ops: Value = serde_json::from_slice(&ops).unwrap();
+        assert_eq!(
+            ops,
+            json!([
+                {"op": "remove", "path": "/env/b~1c"},
+                {"op": "replace", "path": "/ports", "value": [80, 443]},
+                {"op": "replace", "path": "/replicas", "value": 3},
+                {"op": "add", "path": "/tag", "value": "x"},
+            ])
+        );
+
+        assert_eq!(patch(from, PatchKind::Json, ops).unwrap(), to);
+    }
+}
@@ -0,0 +1,418 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Keywords that carry no validation rules
+const ANNOTATIONS: &[&str] = &[
+    "$schema",
+    "$id",
+    "title",
+    "description",
+    "default",
+    "examples",
+];
+
+/// The subset of JSON Schema that manifests are validated against, keep the
+/// list in the README in sync
+const KEYWORDS: &[&str] = &[
+    "type",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "enum",
+    "const",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "minLength",
+    "maxLength",
+    "minItems",
+    "maxItems",
+];
+
+/// Names accepted by the `type` keyword
+const TYPES: &[&str] = &[
+    "object", "array", "string", "boolean", "null", "number", "integer",
+];
+
+/// A resource kind and the JSON Schema its manifests have to satisfy
+#[derive(Debug, Clone, Deserialize)]
+pub struct KindDefinition {
+    pub kind: String,
+    pub schema: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldViolation {
+    pub field: String,
+    pub description: String,
+}
+
+/// Resource kinds supported by the fabric
+#[derive(Debug, Clone, Default)]
+pub struct KindRegistry {
+    kinds: HashMap<String, Value>,
+}
+
+impl KindRegistry {
+    pub fn new(definitions: Vec<KindDefinition>) -> Result<Self> {
+        let mut kinds = HashMap::new();
+
+        for def in definitions {
+            check_schema(&def.schema, "#")
+                .with_context(|| format!("invalid schema for kind {}", def.kind))?;
+
+            if kinds.insert(def.kind.clone(), def.schema).is_some() {
+                bail!("kind {} is defined more than once", def.kind)
+            }
+        }
+
+        Ok(Self { kinds })
+    }
+
+    /// Loads every `.json` file in the directory as a kind definition
+    pub fn from_dir(path: &Path) -> Result<Self> {
+        let mut definitions = vec![];
+
+        for entry in std::fs::read_dir(path).context("reading kinds directory")? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|x| x.to_str()) != Some("json") {
+                continue;
+            }
+
+            let file = std::fs::read(&path)?;
+            let def = serde_json::from_slice(&file)
+                .with_context(|| format!("parsing kind definition {}", path.display()))?;
+
+            definitions.push(def);
+        }
+
+        Self::new(definitions)
+    }
+
+    pub fn contains(&self, kind: &str) -> bool {
+        self.kinds.contains_key(kind)
+    }
+
+    /// Returns every field of the manifest that breaks the schema of the kind
+    pub fn validate(&self, kind: &str, manifest: &Value) -> Result<Vec<FieldViolation>> {
+        let schema = self
+            .kinds
+            .get(kind)
+            .ok_or_else(|| anyhow!("unknown resource kind {kind}"))?;
+
+        let mut violations = vec![];
+        validate(schema, manifest, "", &mut violations);
+
+        Ok(violations)
+    }
+}
+
+fn check_schema(schema: &Value, path: &str) -> Result<()> {
+    let schema = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(x) => x,
+        _ => bail!("schema at {path} must be an object or a boolean"),
+    };
+
+    for (key, value) in schema {
+        if ANNOTATIONS.contains(&key.as_str()) {
+            continue;
+        }
+
+        if !KEYWORDS.contains(&key.as_str()) {
+            bail!("unsupported keyword {key} at {path}")
+        }
+
+        check_keyword(key, value, path)?;
+
+        match key.as_str() {
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .ok_or_else(|| anyhow!("properties at {path} must be an object"))?;
+
+                for (name, x) in properties {
+                    check_schema(x, &format!("{path}/properties/{name}"))?;
+                }
+            }
+            "additionalProperties" | "items" => check_schema(value, &format!("{path}/{key}"))?,
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the value of a keyword whose schemas aren't checked recursively
+fn check_keyword(key: &str, value: &Value, path: &str) -> Result<()> {
+    let is_type = |x: &Value| x.as_str().is_some_and(|x| TYPES.contains(&x));
+
+    let valid = match key {
+        "type" => match value {
+            Value::Array(x) => x.iter().all(is_type),
+            x => is_type(x),
+        },
+        "required" => value
+            .as_array()
+            .is_some_and(|x| x.iter().all(Value::is_string)),
+        "enum" => value.is_array(),
+        "minimum" | "maximum" | "exclusiveMinimum" | "exclusiveMaximum" => value.is_number(),
+        "minLength" | "maxLength" | "minItems" | "maxItems" => value.is_u64(),
+        _ => true,
+    };
+
+    if !valid {
+        bail!("invalid value {value} for keyword {key} at {path}")
+    }
+
+    Ok(())
+}
+
+fn child(path: &str, name: &str) -> String {
+    match path {
+        "" => name.to_owned(),
+        x => format!("{x}.{name}"),
+    }
+}
+
+fn violation(out: &mut Vec<FieldViolation>, path: &str, description: impl Into<String>) {
+    out.push(FieldViolation {
+        field: path.to_owned(),
+        description: description.into(),
+    });
+}
+
+fn is_type(name: &str, value: &Value) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|x| x.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+fn type_matches(expected: &Value, value: &Value) -> bool {
+    match expected {
+        Value::String(x) => is_type(x, value),
+        Value::Array(x) => x
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|x| is_type(x, value)),
+        _ => true,
+    }
+}
+
+fn validate(schema: &Value, value: &Value, path: &str, out: &mut Vec<FieldViolation>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => return violation(out, path, "no value is allowed here"),
+        Value::Object(x) => x,
+        _ => return,
+    };
+
+    if let Some(expected) = schema.get("type") {
+        if !type_matches(expected, value) {
+            return violation(out, path, format!("expected a value of type {expected}"));
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            violation(out, path, "value isn't one of the allowed options");
+        }
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            violation(out, path, format!("value must be {expected}"));
+        }
+    }
+
+    match value {
+        Value::Number(x) => validate_number(schema, x.as_f64().unwrap_or_default(), path, out),
+        Value::String(x) => validate_length(schema, x.chars().count(), "Length", path, out),
+        Value::Array(items) => {
+            validate_length(schema, items.len(), "Items", path, out);
+
+            if let Some(item_schema) = schema.get("items") {
+                for (i, x) in items.iter().enumerate() {
+                    validate(item_schema, x, &format!("{path}[{i}]"), out);
+                }
+            }
+        }
+        Value::Object(map) => validate_object(schema, map, path, out),
+        _ => (),
+    }
+}
+
+fn validate_number(
+    schema: &Map<String, Value>,
+    value: f64,
+    path: &str,
+    out: &mut Vec<FieldViolation>,
+) {
+    let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+
+    if let Some(min) = bound("minimum").filter(|min| value < *min) {
+        violation(out, path, format!("must be at least {min}"));
+    }
+
+    if let Some(max) = bound("maximum").filter(|max| value > *max) {
+        violation(out, path, format!("must be at most {max}"));
+    }
+
+    if let Some(min) = bound("exclusiveMinimum").filter(|min| value <= *min) {
+        violation(out, path, format!("must be greater than {min}"));
+    }
+
+    if let Some(max) = bound("exclusiveMaximum").filter(|max| value >= *max) {
+        violation(out, path, format!("must be less than {max}"));
+    }
+}
+
+fn validate_length(
+    schema: &Map<String, Value>,
+    len: usize,
+    suffix: &str,
+    path: &str,
+    out: &mut Vec<FieldViolation>,
+) {
+    let bound = |key: String| schema.get(&key).and_then(Value::as_u64);
+
+    let unit = match suffix {
+        "Length" => "characters",
+        _ => "items",
+    };
+
+    if let Some(min) = bound(format!("min{suffix}")).filter(|min| (len as u64) < *min) {
+        violation(out, path, format!("must have at least {min} {unit}"));
+    }
+
+    if let Some(max) = bound(format!("max{suffix}")).filter(|max| (len as u64) > *max) {
+        violation(out, path, format!("must have at most {max} {unit}"));
+    }
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    map: &Map<String, Value>,
+    path: &str,
+    out: &mut Vec<FieldViolation>,
+) {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !map.contains_key(name) {
+                violation(out, &child(path, name), "required field is missing");
+            }
+        }
+    }
+
+    let properties = schema.get("properties").and_then(Value::as_object);
+
+    for (name, x) in map {
+        let path = child(path, name);
+
+        match properties.and_then(|p| p.get(name)) {
+            Some(property) => validate(property, x, &path, out),
+            None => match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => violation(out, &path, "unknown field"),
+                Some(additional) => validate(additional, x, &path, out),
+                None => (),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn registry() -> KindRegistry {
+        KindRegistry::new(vec![KindDefinition {
+            kind: "workers.demeter.run/v1alpha1".into(),
+            schema: json!({
+                "type": "object",
+                "required": ["image"],
+                "properties": {
+                    "image": {"type": "string", "minLength": 1},
+                    "replicas": {"type": "integer", "minimum": 0, "maximum": 10},
+                    "ports": {"type": "array", "items": {"type": "integer"}},
+                },
+                "additionalProperties": false,
+            }),
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_manifests_pass() {
+        let violations = registry()
+            .validate(
+                "workers.demeter.run/v1alpha1",
+                &json!({"image": "nginx", "replicas": 2, "ports": [80]}),
+            )
+            .unwrap();
+
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn violations_point_at_fields() {
+        let violations = registry()
+            .validate(
+                "workers.demeter.run/v1alpha1",
+                &json!({"replicas": 20, "ports": [80, "https"], "extra": true}),
+            )
+            .unwrap();
+
+        let fields: Vec<_> = violations.iter().map(|x| x.field.as_str()).collect();
+        assert_eq!(fields, vec!["image", "extra", "ports[1]", "replicas"]);
+
+        assert!(registry().validate("unknown/v1", &json!({})).is_err());
+    }
+
+    #[test]
+    fn unsupported_keywords_are_rejected() {
+        let result = KindRegistry::new(vec![KindDefinition {
+            kind: "x".into(),
+            schema: json!({"type": "string", "pattern": "^a"}),
+        }]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn malformed_keywords_are_rejected() {
+        let load = |schema| {
+            KindRegistry::new(vec![KindDefinition {
+                kind: "x".into(),
+                schema,
+            }])
+            .map_err(|err| format!("{err:#}"))
+        };
+
+        let err = load(json!({"properties": {"replicas": {"minimum": "0"}}})).unwrap_err();
+        assert!(err.contains("keyword minimum at #/properties/replicas"));
+
+        let err = load(json!({"type": "int"})).unwrap_err();
+        assert!(err.contains("keyword type at #"));
+
+        assert!(load(json!({"required": "image"})).is_err());
+        assert!(load(json!({"maxLength": -1})).is_err());
+        assert!(load(json!({"type": ["string", "null"], "enum": ["a", null]})).is_ok());
+    }
+}
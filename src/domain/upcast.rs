@@ -0,0 +1,148 @@
+//! Upcasting of superseded event versions
+//!
+//! Stored events are read in the version they were written with. Superseded
+//! versions go through a chain of upcasters, each taking an event to its next
+//! version, until they reach the shape held by `Event`. Handlers only ever see
+//! the latest versions.
+//!
+//! Superseding an event means adding its new version to `Event`, moving the
+//! old one to `LegacyEvent` and registering an upcaster from it. Upcasters of
+//! older versions are left as they are, their output gets upcast in turn.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{Event, ResourceCreatedV1, ResourceCreatedV2, ResourcePatchedV1, ResourcePatchedV2};
+
+/// Event versions that have been superseded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LegacyEvent {
+    ResourceCreatedV1(ResourceCreatedV1),
+    ResourcePatchedV1(ResourcePatchedV1),
+}
+
+impl LegacyEvent {
+    pub fn type_tag(&self) -> &'static str {
+        match self {
+            LegacyEvent::ResourceCreatedV1(_) => "ResourceCreatedV1",
+            LegacyEvent::ResourcePatchedV1(_) => "ResourcePatchedV1",
+        }
+    }
+}
+
+/// An event in whatever version it was stored with
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionedEvent {
+    Current(Event),
+    Legacy(LegacyEvent),
+}
+
+/// Takes a superseded event to its next version
+pub type Upcaster = fn(LegacyEvent) -> Result<VersionedEvent>;
+
+/// Upcasters keyed by the type tag of the version they take
+const UPCASTERS: &[(&str, Upcaster)] = &[
+    ("ResourceCreatedV1", resource_created_v1),
+    ("ResourcePatchedV1", resource_patched_v1),
+];
+
+fn resource_created_v1(event: LegacyEvent) -> Result<VersionedEvent> {
+    let LegacyEvent::ResourceCreatedV1(x) = event else {
+        bail!("expected ResourceCreatedV1, got {}", event.type_tag())
+    };
+
+    let next = ResourceCreatedV2 {
+        metadata: x.metadata,
+        manifest: x.manifest,
+        timestamp: None,
+    };
+
+    Ok(VersionedEvent::Current(next.into()))
+}
+
+fn resource_patched_v1(event: LegacyEvent) -> Result<VersionedEvent> {
+    let LegacyEvent::ResourcePatchedV1(x) = event else {
+        bail!("expected ResourcePatchedV1, got {}", event.type_tag())
+    };
+
+    let next = ResourcePatchedV2 {
+        metadata: x.metadata,
+        manifest: x.manifest,
+        revision: x.revision,
+        timestamp: None,
+    };
+
+    Ok(VersionedEvent::Current(next.into()))
+}
+
+/// Whether the type tag names a superseded event version
+pub fn is_legacy_tag(tag: &str) -> bool {
+    UPCASTERS.iter().any(|(x, _)| *x == tag)
+}
+
+/// Takes an event to its latest version
+pub fn upcast(event: VersionedEvent) -> Result<Event> {
+    let mut event = event;
+
+    loop {
+        let legacy = match event {
+            VersionedEvent::Current(x) => return Ok(x),
+            VersionedEvent::Legacy(x) => x,
+        };
+
+        let tag = legacy.type_tag();
+
+        let (_, upcaster) = UPCASTERS
+            .iter()
+            .find(|(x, _)| *x == tag)
+            .ok_or_else(|| anyhow!("no upcaster for {tag}"))?;
+
+        event = upcaster(legacy)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ResourceMetadataV1;
+
+    #[test]
+    fn superseded_events_reach_their_latest_version() {
+        let metadata = ResourceMetadataV1 {
+            namespace: "ns1".into(),
+            kind: "workers.demeter.run/v1alpha1".into(),
+            name: "worker-1".into(),
+            uuid: b"uuid-1".to_vec(),
+            labels: Default::default(),
+            annotations: Default::default(),
+        };
+
+        let event = upcast(VersionedEvent::Legacy(LegacyEvent::ResourcePatchedV1(
+            ResourcePatchedV1 {
+                metadata: metadata.clone(),
+                manifest: b"{}".to_vec(),
+                revision: 3,
+            },
+        )))
+        .unwrap();
+
+        let expected: Event = ResourcePatchedV2 {
+            metadata,
+            manifest: b"{}".to_vec(),
+            revision: 3,
+            timestamp: None,
+        }
+        .into();
+
+        assert_eq!(event, expected);
+
+        // current events go through untouched
+        assert_eq!(
+            upcast(VersionedEvent::Current(expected.clone())).unwrap(),
+            expected
+        );
+
+        assert!(is_legacy_tag("ResourceCreatedV1"));
+        assert!(!is_legacy_tag("ResourceCreatedV2"));
+    }
+}
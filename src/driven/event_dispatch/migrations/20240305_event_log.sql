@@ -0,0 +1,7 @@
+-- every event ever dispatched, in dispatch order. Rows are only appended.
+CREATE TABLE IF NOT EXISTS events (
+    sequence INTEGER PRIMARY KEY,
+    receipt BLOB NOT NULL UNIQUE,
+    payload BLOB NOT NULL,
+    timestamp INTEGER NOT NULL
+);
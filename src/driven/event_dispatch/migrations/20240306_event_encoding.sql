@@ -0,0 +1,3 @@
+-- events used to be logged as json, they're now written with the versioned
+-- binary encoding. Both keep being readable.
+ALTER TABLE events ADD COLUMN encoding INTEGER NOT NULL DEFAULT 0;
@@ -1,35 +1,521 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
 use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
 
-use crate::domain::Event;
+use crate::domain::{decode_event, decode_json_event, encode_event, unix_now, Event};
 
 pub type EventReceipt = Vec<u8>;
 
+/// Position of an event in the order it was dispatched, starting at 1
+pub type EventSequence = u64;
+
+/// Generates a unique receipt for an event entering the fabric
+pub fn new_receipt() -> EventReceipt {
+    uuid::Uuid::new_v4().into_bytes().to_vec()
+}
+
+#[derive(Debug, Clone)]
+pub struct EventWrapper(pub Event, pub EventReceipt, pub EventSequence);
+
+/// Where a subscription starts from
 #[derive(Debug, Clone)]
-pub struct EventWrapper(pub Event, pub EventReceipt);
+pub enum ResumeFrom {
+    /// Only events dispatched after subscribing
+    Now,
+    /// Events after the one with the given sequence
+    Sequence(EventSequence),
+    /// Events after the one with the given receipt
+    Receipt(EventReceipt),
+}
+
+/// Raised when a subscriber can't pick up where it left off, either because
+/// the events it needs are no longer retained or because it fell behind
+#[derive(Debug, thiserror::Error)]
+#[error("resync required: {0}")]
+pub struct ResyncRequired(pub String);
+
+/// Max number of logged events handed to a subscriber catching up, those
+/// further behind have to resync
+pub const MAX_CATCH_UP: i64 = 10_000;
+
+/// Payload encodings found in the log
+const ENCODING_JSON: i64 = 0;
+const ENCODING_BINARY: i64 = 1;
+
+#[derive(Debug, sqlx::FromRow)]
+struct LoggedEvent {
+    sequence: i64,
+    receipt: Vec<u8>,
+    payload: Vec<u8>,
+    encoding: i64,
+}
+
+impl TryFrom<LoggedEvent> for EventWrapper {
+    type Error = anyhow::Error;
+
+    fn try_from(value: LoggedEvent) -> Result<Self> {
+        let event = match value.encoding {
+            ENCODING_JSON => decode_json_event(&value.payload)?,
+            ENCODING_BINARY => decode_event(&value.payload)?,
+            x => bail!("unknown encoding {x} for event {}", value.sequence),
+        };
+
+        Ok(EventWrapper(event, value.receipt, value.sequence as u64))
+    }
+}
+
+/// Append-only record of every dispatched event, kept in sqlite
+#[derive(Clone)]
+struct EventLog {
+    db: sqlx::sqlite::SqlitePool,
+}
+
+impl EventLog {
+    async fn open(path: &Path) -> Result<Self> {
+        // an event counts as dispatched once it's in the log, so commits have
+        // to survive a crash
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Full);
+
+        let db = SqlitePoolOptions::new().connect_with(options).await?;
+
+        sqlx::migrate!("src/driven/event_dispatch/migrations")
+            .run(&db)
+            .await?;
+
+        Ok(Self { db })
+    }
+
+    async fn append(&self, wrapper: &EventWrapper) -> Result<()> {
+        let EventWrapper(event, receipt, sequence) = wrapper;
+
+        let sequence = *sequence as i64;
+        let payload = encode_event(event);
+        let timestamp = unix_now()? as i64;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO events (sequence, receipt, payload, encoding, timestamp)
+                VALUES ($1, $2, $3, $4, $5);
+            "#,
+            sequence,
+            receipt,
+            payload,
+            ENCODING_BINARY,
+            timestamp,
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn last_sequence(&self) -> Result<EventSequence> {
+        let last: Option<i64> = sqlx::query_scalar("SELECT MAX(sequence) FROM events;")
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(last.unwrap_or_default() as u64)
+    }
+
+    async fn find_receipt(&self, receipt: &[u8]) -> Result<Option<EventSequence>> {
+        let sequence: Option<i64> =
+            sqlx::query_scalar("SELECT sequence FROM events WHERE receipt = $1;")
+                .bind(receipt)
+                .fetch_optional(&self.db)
+                .await?;
+
+        Ok(sequence.map(|x| x as u64))
+    }
+
+    async fn read_after(&self, after: EventSequence, limit: i64) -> Result<Vec<EventWrapper>> {
+        let rows = sqlx::query_as::<_, LoggedEvent>(
+            "SELECT sequence, receipt, payload, encoding FROM events WHERE sequence > $1 ORDER BY sequence LIMIT $2;",
+        )
+        .bind(after as i64)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        rows.into_iter().map(EventWrapper::try_from).collect()
+    }
+}
+
+/// Events dispatched recently, kept so that subscribers can resume
+struct Backlog {
+    next_sequence: EventSequence,
+    recent: VecDeque<EventWrapper>,
+}
 
 #[derive(Clone)]
 pub struct EventDispatch {
     pub sender: tokio::sync::broadcast::Sender<EventWrapper>,
+    capacity: usize,
+    backlog: Arc<Mutex<Backlog>>,
+    log: Option<EventLog>,
 }
 
 impl EventDispatch {
-    pub fn ephemeral(capacity: usize) -> Self {
+    fn new(capacity: usize, next_sequence: EventSequence, log: Option<EventLog>) -> Self {
         let (sender, _) = tokio::sync::broadcast::channel(capacity);
 
-        Self { sender }
+        let backlog = Backlog {
+            next_sequence,
+            recent: VecDeque::with_capacity(capacity),
+        };
+
+        Self {
+            sender,
+            capacity,
+            backlog: Arc::new(Mutex::new(backlog)),
+            log,
+        }
+    }
+
+    /// Dispatches events in memory only, they're lost once the process exits
+    pub fn ephemeral(capacity: usize) -> Self {
+        Self::new(capacity, 1, None)
+    }
+
+    /// Dispatches events through an append-only log stored at the given path,
+    /// picking up the sequence where the log left off
+    pub async fn persistent(path: &Path, capacity: usize) -> Result<Self> {
+        let log = EventLog::open(path).await?;
+        let next_sequence = log.last_sequence().await? + 1;
+
+        Ok(Self::new(capacity, next_sequence, Some(log)))
+    }
+
+    /// Whether events are kept in a log that they can be read back from
+    pub fn is_logged(&self) -> bool {
+        self.log.is_some()
     }
 
     pub fn subscribe(&mut self) -> Receiver<EventWrapper> {
         self.sender.subscribe()
     }
 
-    pub fn submit_event(&mut self, event: impl Into<Event>) -> Result<EventReceipt> {
-        let rcpt = uuid::Uuid::new_v4().into_bytes().to_vec();
-        let wrapper = EventWrapper(event.into(), rcpt.clone());
+    /// Subscribes to new events and returns the retained events that come
+    /// after the resume point, which have to be processed first
+    pub async fn subscribe_from(
+        &self,
+        from: &ResumeFrom,
+    ) -> Result<(VecDeque<EventWrapper>, Receiver<EventWrapper>)> {
+        // holding the lock keeps events from slipping between the backlog and
+        // the subscription
+        let backlog = self.backlog.lock().await;
+        let receiver = self.sender.subscribe();
+
+        let sequence = match from {
+            ResumeFrom::Now => return Ok((VecDeque::new(), receiver)),
+            ResumeFrom::Sequence(x) => *x,
+            ResumeFrom::Receipt(receipt) => {
+                let retained = backlog.recent.iter().find(|x| &x.1 == receipt);
+
+                let logged = match (retained, &self.log) {
+                    (Some(x), _) => Some(x.2),
+                    (None, Some(log)) => log.find_receipt(receipt).await?,
+                    (None, None) => None,
+                };
+
+                logged.ok_or_else(|| ResyncRequired("event receipt isn't retained".into()))?
+            }
+        };
+
+        let oldest = backlog
+            .recent
+            .front()
+            .map(|x| x.2)
+            .unwrap_or(backlog.next_sequence);
 
-        self.sender.send(wrapper)?;
+        if sequence + 1 >= oldest {
+            let pending = backlog
+                .recent
+                .iter()
+                .filter(|x| x.2 > sequence)
+                .cloned()
+                .collect();
+
+            return Ok((pending, receiver));
+        }
+
+        let log = match &self.log {
+            Some(x) => x,
+            None => {
+                let msg = format!("event {sequence} is no longer retained");
+                return Err(ResyncRequired(msg).into());
+            }
+        };
+
+        let behind = backlog.next_sequence - 1 - sequence;
+
+        if behind > MAX_CATCH_UP as u64 {
+            let msg = format!("event {sequence} is {behind} events behind");
+            return Err(ResyncRequired(msg).into());
+        }
+
+        let pending = log.read_after(sequence, MAX_CATCH_UP).await?.into();
+
+        Ok((pending, receiver))
+    }
+
+    /// Reads up to `limit` logged events that come after the given sequence
+    pub async fn read_log(&self, after: EventSequence, limit: i64) -> Result<Vec<EventWrapper>> {
+        match &self.log {
+            Some(log) => log.read_after(after, limit).await,
+            None => Err(ResyncRequired("events aren't logged".into()).into()),
+        }
+    }
+
+    /// Dispatches an event to the subscribers. When backed by a log, the
+    /// receipt is only returned once the event is durably written.
+    pub async fn submit_event(&mut self, event: impl Into<Event>) -> Result<EventReceipt> {
+        let rcpt = new_receipt();
+
+        let mut backlog = self.backlog.lock().await;
+        self.dispatch(&mut backlog, event.into(), rcpt.clone())
+            .await?;
 
         Ok(rcpt)
     }
+
+    /// Dispatches an event that entered the fabric elsewhere under the receipt
+    /// it came with, so that a redelivery after a crash or a resync can be
+    /// told apart from a new event. Returns `false` without dispatching it if
+    /// the receipt is in the log or the backlog. Without a log, older
+    /// redeliveries still reach the subscribers, and the projections skip
+    /// them by their receipt.
+    pub async fn ingest_event(
+        &mut self,
+        event: impl Into<Event>,
+        receipt: EventReceipt,
+    ) -> Result<bool> {
+        let mut backlog = self.backlog.lock().await;
+
+        let retained = backlog.recent.iter().any(|x| x.1 == receipt);
+
+        let logged = match (retained, &self.log) {
+            (false, Some(log)) => log.find_receipt(&receipt).await?.is_some(),
+            _ => false,
+        };
+
+        if retained || logged {
+            return Ok(false);
+        }
+
+        self.dispatch(&mut backlog, event.into(), receipt).await?;
+
+        Ok(true)
+    }
+
+    async fn dispatch(
+        &self,
+        backlog: &mut Backlog,
+        event: Event,
+        receipt: EventReceipt,
+    ) -> Result<()> {
+        let wrapper = EventWrapper(event, receipt, backlog.next_sequence);
+
+        match &self.log {
+            Some(log) => {
+                log.append(&wrapper).await?;
+
+                // subscribers that aren't listening can catch up from the log
+                let _ = self.sender.send(wrapper.clone());
+            }
+            None => {
+                self.sender.send(wrapper.clone())?;
+            }
+        }
+
+        backlog.next_sequence += 1;
+
+        if backlog.recent.len() == self.capacity {
+            backlog.recent.pop_front();
+        }
+
+        backlog.recent.push_back(wrapper);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::NamespaceMintedV1;
+
+    async fn submit(dispatch: &mut EventDispatch, name: &str) -> EventReceipt {
+        let _receiver = dispatch.subscribe();
+
+        dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: name.into(),
+                root_public_key: vec![],
+            })
+            .await
+            .unwrap()
+    }
+
+    fn sequences(pending: &VecDeque<EventWrapper>) -> Vec<EventSequence> {
+        pending.iter().map(|x| x.2).collect()
+    }
+
+    #[tokio::test]
+    async fn subscriptions_resume_from_the_backlog() {
+        let mut dispatch = EventDispatch::ephemeral(2);
+
+        submit(&mut dispatch, "ns1").await;
+        let second = submit(&mut dispatch, "ns2").await;
+        submit(&mut dispatch, "ns3").await;
+
+        let (pending, _) = dispatch
+            .subscribe_from(&ResumeFrom::Sequence(2))
+            .await
+            .unwrap();
+        assert_eq!(sequences(&pending), vec![3]);
+
+        let (pending, _) = dispatch
+            .subscribe_from(&ResumeFrom::Receipt(second))
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+
+        let (pending, _) = dispatch.subscribe_from(&ResumeFrom::Now).await.unwrap();
+        assert!(pending.is_empty());
+
+        // the first event no longer fits in the backlog
+        let err = dispatch
+            .subscribe_from(&ResumeFrom::Sequence(0))
+            .await
+            .unwrap_err();
+        assert!(err.is::<ResyncRequired>());
+    }
+
+    #[tokio::test]
+    async fn json_events_are_still_read() {
+        let path = std::env::temp_dir().join(format!("events-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let dispatch = EventDispatch::persistent(&path, 2).await.unwrap();
+        let log = dispatch.log.clone().unwrap();
+
+        // as written before the binary encoding
+        let event: Event = NamespaceMintedV1 {
+            name: "ns1".into(),
+            root_public_key: vec![],
+        }
+        .into();
+
+        sqlx::query(
+            "INSERT INTO events (sequence, receipt, payload, timestamp) VALUES (1, $1, $2, 0);",
+        )
+        .bind(new_receipt())
+        .bind(serde_json::to_vec(&event).unwrap())
+        .execute(&log.db)
+        .await
+        .unwrap();
+
+        drop(dispatch);
+
+        let mut dispatch = EventDispatch::persistent(&path, 2).await.unwrap();
+        submit(&mut dispatch, "ns2").await;
+
+        let events = dispatch.read_log(0, 10).await.unwrap();
+        assert_eq!(events[0].0, event);
+        assert_eq!(events[1].2, 2);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn events_outlive_the_process() {
+        let path = std::env::temp_dir().join(format!("events-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let mut dispatch = EventDispatch::persistent(&path, 2).await.unwrap();
+
+        let first = submit(&mut dispatch, "ns1").await;
+
+        // nobody is listening, but the event is still logged
+        dispatch
+            .submit_event(NamespaceMintedV1 {
+                name: "ns2".into(),
+                root_public_key: vec![],
+            })
+            .await
+            .unwrap();
+
+        drop(dispatch);
+
+        let mut dispatch = EventDispatch::persistent(&path, 2).await.unwrap();
+        submit(&mut dispatch, "ns3").await;
+        submit(&mut dispatch, "ns4").await;
+
+        // older events than the backlog holds are read from the log
+        let (pending, _) = dispatch
+            .subscribe_from(&ResumeFrom::Receipt(first))
+            .await
+            .unwrap();
+        assert_eq!(sequences(&pending), vec![2, 3, 4]);
+
+        match &pending[0].0 {
+            Event::NamespaceMintedV1(x) => assert_eq!(x.name, "ns2"),
+            x => panic!("unexpected event {x:?}"),
+        }
+
+        let (pending, _) = dispatch
+            .subscribe_from(&ResumeFrom::Sequence(0))
+            .await
+            .unwrap();
+        assert_eq!(sequences(&pending), vec![1, 2, 3, 4]);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn ingested_events_are_dispatched_once() {
+        let path = std::env::temp_dir().join(format!("events-{}.sqlite", uuid::Uuid::new_v4()));
+
+        let mut dispatch = EventDispatch::persistent(&path, 1).await.unwrap();
+        let _receiver = dispatch.subscribe();
+
+        let minted = NamespaceMintedV1 {
+            name: "ns1".into(),
+            root_public_key: vec![],
+        };
+
+        let receipt = new_receipt();
+        assert!(dispatch
+            .ingest_event(minted.clone(), receipt.clone())
+            .await
+            .unwrap());
+        assert!(!dispatch
+            .ingest_event(minted.clone(), receipt.clone())
+            .await
+            .unwrap());
+
+        // once out of the backlog, the receipt is found in the log
+        submit(&mut dispatch, "ns2").await;
+        assert!(!dispatch.ingest_event(minted, receipt).await.unwrap());
+
+        let (pending, _) = dispatch
+            .subscribe_from(&ResumeFrom::Sequence(0))
+            .await
+            .unwrap();
+        assert_eq!(sequences(&pending), vec![1, 2]);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
 }
@@ -0,0 +1 @@
+ALTER TABLE namespaces ADD COLUMN root_public_key BLOB;
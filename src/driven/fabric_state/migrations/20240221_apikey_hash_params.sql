@@ -0,0 +1,5 @@
+ALTER TABLE apikeys ADD COLUMN algorithm TEXT NOT NULL DEFAULT 'argon2id';
+ALTER TABLE apikeys ADD COLUMN version INTEGER NOT NULL DEFAULT 19;
+ALTER TABLE apikeys ADD COLUMN m_cost INTEGER NOT NULL DEFAULT 19456;
+ALTER TABLE apikeys ADD COLUMN t_cost INTEGER NOT NULL DEFAULT 2;
+ALTER TABLE apikeys ADD COLUMN p_cost INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+ALTER TABLE apikeys ADD COLUMN key_id BLOB DEFAULT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS apikeys_namespace_key_id ON apikeys (namespace, key_id);
@@ -0,0 +1 @@
+ALTER TABLE apikeys ADD COLUMN revoked BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE apikeys ADD COLUMN not_before INTEGER DEFAULT NULL;
+ALTER TABLE apikeys ADD COLUMN not_after INTEGER DEFAULT NULL;
@@ -0,0 +1,2 @@
+-- keys registered before scopes existed are left unrestricted (NULL)
+ALTER TABLE apikeys ADD COLUMN scopes TEXT DEFAULT NULL;
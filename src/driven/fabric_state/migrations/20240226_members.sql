@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS members (
+    namespace TEXT,
+    public_key BLOB,
+    role TEXT,
+    PRIMARY KEY (namespace, public_key),
+    FOREIGN KEY (namespace) REFERENCES namespaces(name)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    namespace TEXT NOT NULL,
+    credential_kind TEXT NOT NULL,
+    key_id BLOB NULL,
+    operation TEXT NOT NULL,
+    resource_uuid BLOB NULL,
+    outcome TEXT NOT NULL,
+    error TEXT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_namespace ON audit_log (namespace, id);
@@ -0,0 +1,2 @@
+-- latest status reported for the resource, NULL until the first report
+ALTER TABLE resources ADD COLUMN status BLOB NULL;
@@ -0,0 +1,2 @@
+-- resources start at revision 1 and every patch bumps it by one
+ALTER TABLE resources ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,31 @@
+ALTER TABLE resources ADD COLUMN deleting BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- finalizers that have to clear before a deleting resource is removed
+CREATE TABLE IF NOT EXISTS resource_finalizers (
+    resource BLOB,
+    finalizer TEXT,
+    PRIMARY KEY (resource, finalizer),
+    FOREIGN KEY (resource) REFERENCES resources(uuid)
+);
+
+-- usage has to be kept for billing after its resource is removed, so the
+-- accounting table is rebuilt without the foreign key on resources
+CREATE TABLE accounting_new (
+    id INTEGER PRIMARY KEY,
+    epoch INTEGER,
+    entry BLOB,
+    cluster BLOB,
+    namespace TEXT,
+    resource BLOB DEFAULT NULL,
+    account INTEGER,
+    debit INTEGER NULL,
+    credit INTEGER NULL,
+    FOREIGN KEY (namespace) REFERENCES namespaces(name)
+);
+
+INSERT INTO accounting_new (id, epoch, entry, cluster, namespace, resource, account, debit, credit)
+SELECT id, epoch, entry, cluster, namespace, resource, account, debit, credit FROM accounting;
+
+DROP TABLE accounting;
+
+ALTER TABLE accounting_new RENAME TO accounting;
@@ -0,0 +1,13 @@
+-- names used to be unrestricted, duplicates created back then are left as they
+-- are and lookups by name resolve to the oldest one. The projection keeps new
+-- duplicates out, so the index can't be unique.
+CREATE INDEX IF NOT EXISTS resources_namespace_kind_name ON resources (namespace, kind, name);
+
+-- creates that lost to an existing resource with the same kind and name
+CREATE TABLE IF NOT EXISTS rejected_resources (
+    uuid BLOB PRIMARY KEY,
+    namespace TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    event_receipt BLOB NOT NULL
+);
@@ -0,0 +1,16 @@
+-- every manifest a resource has gone through. Rows aren't tied to the
+-- resources table so that the history outlives the resource.
+CREATE TABLE IF NOT EXISTS resource_revisions (
+    resource BLOB NOT NULL,
+    revision INTEGER NOT NULL,
+    manifest BLOB NOT NULL,
+    event_receipt BLOB NULL,
+    timestamp INTEGER NULL,
+    PRIMARY KEY (resource, revision)
+);
+
+-- the events behind existing manifests are unknown, so only the current
+-- revision of each resource is carried over
+INSERT OR IGNORE INTO resource_revisions (resource, revision, manifest)
+SELECT uuid, revision, manifest FROM resources
+WHERE manifest IS NOT NULL;
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS resource_labels (
+    resource BLOB NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (resource, key),
+    FOREIGN KEY (resource) REFERENCES resources(uuid) ON DELETE CASCADE
+);
+
+-- selectors look resources up by label rather than labels by resource
+CREATE INDEX IF NOT EXISTS resource_labels_key_value ON resource_labels (key, value);
+
+CREATE TABLE IF NOT EXISTS resource_annotations (
+    resource BLOB NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (resource, key),
+    FOREIGN KEY (resource) REFERENCES resources(uuid) ON DELETE CASCADE
+);
@@ -0,0 +1,6 @@
+-- sequence of the last logged event applied to the projections, so that a
+-- restart only has to catch up with the events after it
+CREATE TABLE IF NOT EXISTS projection_checkpoint (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    sequence INTEGER NOT NULL
+);
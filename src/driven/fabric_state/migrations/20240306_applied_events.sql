@@ -0,0 +1,5 @@
+-- receipts of the events reflected in the projections, written in the same
+-- transaction as the projection itself so that redelivered events are skipped
+CREATE TABLE IF NOT EXISTS applied_events (
+    receipt BLOB PRIMARY KEY
+);
@@ -0,0 +1,5 @@
+-- keys registered before key ids existed get one so that they can be rotated
+-- and revoked, the flag keeps them reachable through secret-only tokens
+ALTER TABLE apikeys ADD COLUMN legacy BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE apikeys SET legacy = TRUE, key_id = randomblob(8) WHERE key_id IS NULL;
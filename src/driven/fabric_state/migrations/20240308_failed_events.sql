@@ -0,0 +1,7 @@
+-- logged events that couldn't be projected. They're set aside so that the
+-- checkpoint can move past them, and kept here for an operator to look into.
+CREATE TABLE IF NOT EXISTS failed_events (
+    receipt BLOB PRIMARY KEY,
+    sequence INTEGER NOT NULL,
+    error TEXT NOT NULL
+);
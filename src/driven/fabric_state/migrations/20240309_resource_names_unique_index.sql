@@ -0,0 +1,15 @@
+-- duplicates created back when names were unrestricted, all but the oldest of
+-- each (namespace, kind, name), are flagged so that the index can leave them
+-- out and still be unique for every other row
+ALTER TABLE resources ADD COLUMN legacy_duplicate BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE resources SET legacy_duplicate = TRUE
+WHERE rowid NOT IN (
+    SELECT MIN(rowid) FROM resources
+    GROUP BY namespace, kind, name
+);
+
+DROP INDEX IF EXISTS resources_namespace_kind_name;
+
+CREATE UNIQUE INDEX IF NOT EXISTS resources_namespace_kind_name
+ON resources (namespace, kind, name) WHERE NOT legacy_duplicate;
@@ -0,0 +1,8 @@
+-- signatures accepted recently, kept until their timestamp goes stale so that
+-- each one can only be used once, restarts included
+CREATE TABLE IF NOT EXISTS seen_signatures (
+    signature TEXT PRIMARY KEY,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS seen_signatures_timestamp ON seen_signatures (timestamp);
@@ -1,14 +1,44 @@
 use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use crate::domain::{LabelOperator, LabelSelector};
+
+#[derive(Clone)]
 pub struct FabricState {
     db: sqlx::sqlite::SqlitePool,
 }
 
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ApiKeyHashing {
+    pub algorithm: String,
+    pub version: i64,
+    pub m_cost: i64,
+    pub t_cost: i64,
+    pub p_cost: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct ApiKeyValidity {
+    pub not_before: Option<i64>,
+    pub not_after: Option<i64>,
+}
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct ApiKey {
+    pub key_id: Option<Vec<u8>>,
     pub digest: Vec<u8>,
     pub salt: Vec<u8>,
+    #[sqlx(flatten)]
+    pub hashing: ApiKeyHashing,
+    #[sqlx(flatten)]
+    pub validity: ApiKeyValidity,
+    /// Comma-separated list of scopes, `None` for unrestricted legacy keys
+    pub scopes: Option<String>,
+    pub revoked: bool,
+    /// Predates key ids, so it's also found through tokens with just a secret
+    pub legacy: bool,
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -16,8 +46,95 @@ pub struct ListResourceProj {
     pub name: String,
     pub uuid: Vec<u8>,
     pub kind: String,
+    pub deleting: bool,
+    pub manifest: Vec<u8>,
+    pub status: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct AuditRecord {
+    pub timestamp: i64,
+    pub namespace: String,
+    pub credential_kind: String,
+    pub key_id: Option<Vec<u8>>,
+    pub operation: String,
+    pub resource_uuid: Option<Vec<u8>>,
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuditRow {
+    pub id: i64,
+    #[sqlx(flatten)]
+    pub record: AuditRecord,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ResourceRow {
+    pub uuid: Vec<u8>,
+    pub namespace: String,
+    pub name: String,
+    pub kind: String,
+    pub manifest: Vec<u8>,
+    pub status: Option<Vec<u8>>,
+    pub revision: i64,
+    pub deleting: bool,
 }
 
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ResourceRevision {
+    pub resource: Vec<u8>,
+    pub revision: i64,
+    pub manifest: Vec<u8>,
+    /// Receipt of the event that produced the revision, `None` for revisions
+    /// that predate the history
+    pub event_receipt: Option<Vec<u8>>,
+    pub timestamp: Option<i64>,
+}
+
+/// Logged event that couldn't be projected and was set aside
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct FailedEvent {
+    pub receipt: Vec<u8>,
+    pub sequence: i64,
+    pub error: String,
+}
+
+/// Narrows down the resources listed for a namespace
+#[derive(Debug, Default)]
+pub struct ResourceFilter {
+    pub kind: Option<String>,
+    pub name_prefix: Option<String>,
+    pub selector: LabelSelector,
+}
+
+/// Position of a resource within a listing, which is ordered by name and then
+/// by kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceCursor {
+    pub name: String,
+    pub kind: String,
+}
+
+/// Tables derived from events, ordered so that no row is removed before the
+/// rows that reference it
+const PROJECTION_TABLES: [&str; 13] = [
+    "failed_events",
+    "rejected_resources",
+    "resource_labels",
+    "resource_annotations",
+    "resource_finalizers",
+    "resource_revisions",
+    "accounting",
+    "resources",
+    "members",
+    "apikeys",
+    "namespaces",
+    "applied_events",
+    "projection_checkpoint",
+];
+
 pub struct AccountDelta {
     pub account: i64,
     pub debit: Option<i64>,
@@ -25,11 +142,21 @@ pub struct AccountDelta {
 }
 
 impl FabricState {
+    fn new(db: sqlx::sqlite::SqlitePool) -> Self {
+        Self { db }
+    }
+
+    /// Opens a transaction for a set of writes that should land all at once or
+    /// not at all
+    pub async fn begin(&self) -> Result<Transaction<'static, Sqlite>> {
+        Ok(self.db.begin().await?)
+    }
+
     pub async fn open(path: &Path) -> Result<Self> {
         let url = format!("sqlite:{}?mode=rwc", path.display());
         let db = sqlx::sqlite::SqlitePoolOptions::new().connect(&url).await?;
 
-        Ok(Self { db })
+        Ok(Self::new(db))
     }
 
     pub async fn ephemeral() -> Result<Self> {
@@ -37,7 +164,7 @@ impl FabricState {
             .connect("sqlite::memory:")
             .await?;
 
-        let out = Self { db };
+        let out = Self::new(db);
         out.migrate().await?;
 
         Ok(out)
@@ -51,18 +178,26 @@ impl FabricState {
         Ok(())
     }
 
-    pub async fn insert_namespace(&self, name: &str) -> Result<()> {
-        sqlx::query!(
+    /// Returns `false` if the name is already taken
+    pub async fn insert_namespace(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        name: &str,
+        root_public_key: &[u8],
+    ) -> Result<bool> {
+        let result = sqlx::query!(
             r#"
-INSERT INTO namespaces (name) 
-VALUES ($1)
+INSERT INTO namespaces (name, root_public_key) 
+VALUES ($1, $2)
+ON CONFLICT (name) DO NOTHING
 "#,
             name,
+            root_public_key,
         )
-        .execute(&self.db)
+        .execute(&mut **tx)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
     pub async fn namespace_exists(&self, name: &str) -> Result<bool> {
@@ -80,17 +215,48 @@ WHERE name = $1
         Ok(record.is_some())
     }
 
-    pub async fn insert_api_key(&self, ns: &str, digest: &[u8], salt: &[u8]) -> Result<()> {
+    pub async fn get_namespace_root_key(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let row = sqlx::query_as::<_, (Option<Vec<u8>>,)>(
+            r#"
+SELECT root_public_key
+FROM namespaces
+WHERE name = $1
+"#,
+        )
+        .bind(name)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.and_then(|(key,)| key))
+    }
+
+    pub async fn insert_api_key(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        key: &ApiKey,
+    ) -> Result<()> {
         sqlx::query!(
             r#"
-INSERT INTO apikeys (namespace, digest, salt) 
-VALUES ($1, $2, $3)
+INSERT INTO apikeys (namespace, key_id, digest, salt, algorithm, version, m_cost, t_cost, p_cost, not_before, not_after, scopes, revoked, legacy) 
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
 "#,
             ns,
-            digest,
-            salt
+            key.key_id,
+            key.digest,
+            key.salt,
+            key.hashing.algorithm,
+            key.hashing.version,
+            key.hashing.m_cost,
+            key.hashing.t_cost,
+            key.hashing.p_cost,
+            key.validity.not_before,
+            key.validity.not_after,
+            key.scopes,
+            key.revoked,
+            key.legacy,
         )
-        .execute(&self.db)
+        .execute(&mut **tx)
         .await?;
 
         Ok(())
@@ -99,7 +265,7 @@ VALUES ($1, $2, $3)
     pub async fn get_all_api_keys_for_namespace(&self, ns: &str) -> Result<Vec<ApiKey>> {
         let rows = sqlx::query_as::<_, ApiKey>(
             r#"
-SELECT digest, salt
+SELECT key_id, digest, salt, algorithm, version, m_cost, t_cost, p_cost, not_before, not_after, scopes, revoked, legacy
 FROM apikeys
 WHERE namespace = $1
 "#,
@@ -111,114 +277,959 @@ WHERE namespace = $1
         Ok(rows)
     }
 
+    /// Legacy keys of the namespace that could still authenticate at the given
+    /// time, leaving out those that were revoked or rotated away
+    pub async fn get_live_legacy_api_keys(&self, ns: &str, now: i64) -> Result<Vec<ApiKey>> {
+        let rows = sqlx::query_as::<_, ApiKey>(
+            r#"
+SELECT key_id, digest, salt, algorithm, version, m_cost, t_cost, p_cost, not_before, not_after, scopes, revoked, legacy
+FROM apikeys
+WHERE namespace = $1 AND legacy AND NOT revoked AND (not_after IS NULL OR not_after > $2)
+"#,
+        )
+        .bind(ns)
+        .bind(now)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn get_api_key(&self, ns: &str, key_id: &[u8]) -> Result<Option<ApiKey>> {
+        let row = sqlx::query_as::<_, ApiKey>(
+            r#"
+SELECT key_id, digest, salt, algorithm, version, m_cost, t_cost, p_cost, not_before, not_after, scopes, revoked, legacy
+FROM apikeys
+WHERE namespace = $1 AND key_id = $2
+"#,
+        )
+        .bind(ns)
+        .bind(key_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row)
+    }
+
+    pub async fn revoke_api_key(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        key_id: &[u8],
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+UPDATE apikeys
+SET revoked = TRUE
+WHERE namespace = $1 AND key_id = $2
+"#,
+            ns,
+            key_id,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_api_key_expiry(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        key_id: &[u8],
+        not_after: i64,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+UPDATE apikeys
+SET not_after = $3
+WHERE namespace = $1 AND key_id = $2
+"#,
+            ns,
+            key_id,
+            not_after,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_member(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        public_key: &[u8],
+        role: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+INSERT INTO members (namespace, public_key, role) 
+VALUES ($1, $2, $3)
+"#,
+            ns,
+            public_key,
+            role,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_member_role(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        public_key: &[u8],
+        role: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+UPDATE members
+SET role = $3
+WHERE namespace = $1 AND public_key = $2
+"#,
+            ns,
+            public_key,
+            role,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_member(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        public_key: &[u8],
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+DELETE FROM members
+WHERE namespace = $1 AND public_key = $2
+"#,
+            ns,
+            public_key,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_member_role(&self, ns: &str, public_key: &[u8]) -> Result<Option<String>> {
+        let row = sqlx::query_as::<_, (String,)>(
+            r#"
+SELECT role
+FROM members
+WHERE namespace = $1 AND public_key = $2
+"#,
+        )
+        .bind(ns)
+        .bind(public_key)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(|(role,)| role))
+    }
+
+    /// Inserts a resource unless its uuid or its (namespace, kind, name) is
+    /// already taken. Returns whether the row was inserted.
     pub async fn insert_resource(
         &self,
+        tx: &mut Transaction<'_, Sqlite>,
         ns: &str,
         kind: &str,
         uuid: &[u8],
         name: &str,
         manifest: &[u8],
+    ) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+INSERT INTO resources (namespace, kind, uuid, name, manifest) 
+SELECT $1, $2, $3, $4, $5
+WHERE NOT EXISTS (
+    SELECT 1 FROM resources WHERE namespace = $1 AND kind = $2 AND name = $4
+)
+ON CONFLICT DO NOTHING
+"#,
+            ns,
+            kind,
+            uuid,
+            name,
+            manifest
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn insert_rejected_resource(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        kind: &str,
+        uuid: &[u8],
+        name: &str,
+        event_receipt: &[u8],
     ) -> Result<()> {
         sqlx::query!(
             r#"
-INSERT INTO resources (namespace, kind, uuid, name, manifest) 
+INSERT OR IGNORE INTO rejected_resources (namespace, kind, uuid, name, event_receipt)
 VALUES ($1, $2, $3, $4, $5)
 "#,
             ns,
             kind,
             uuid,
             name,
-            manifest
+            event_receipt,
         )
-        .execute(&self.db)
+        .execute(&mut **tx)
         .await?;
 
         Ok(())
     }
 
-    pub async fn insert_accounting(
+    pub async fn resource_was_rejected(&self, uuid: &[u8]) -> Result<bool> {
+        let row = sqlx::query_as::<_, (i64,)>(
+            r#"
+SELECT 1 FROM rejected_resources
+WHERE uuid = $1
+"#,
+        )
+        .bind(uuid)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    pub async fn resource_exists(&self, ns: &str, kind: &str, name: &str) -> Result<bool> {
+        let row = sqlx::query_as::<_, (i64,)>(
+            r#"
+SELECT 1 FROM resources
+WHERE namespace = $1 AND kind = $2 AND name = $3
+"#,
+        )
+        .bind(ns)
+        .bind(kind)
+        .bind(name)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    /// Replaces the manifest of a resource unless it's already at the given
+    /// revision or a later one. Returns whether the row was updated.
+    pub async fn update_resource_manifest(
         &self,
-        epoch: i64,
-        entry: &[u8],
-        cluster: &[u8],
-        namespace: &str,
-        resource: Option<&[u8]>,
-        deltas: Vec<AccountDelta>,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        uuid: &[u8],
+        manifest: &[u8],
+        revision: i64,
+    ) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+UPDATE resources SET manifest = $3, revision = $4
+WHERE namespace = $1 AND uuid = $2 AND revision < $4
+"#,
+            ns,
+            uuid,
+            manifest,
+            revision,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn insert_resource_labels(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        uuid: &[u8],
+        labels: &BTreeMap<String, String>,
+        annotations: &BTreeMap<String, String>,
     ) -> Result<()> {
-        let mut tx = self.db.begin().await?;
+        for (key, value) in labels {
+            sqlx::query!(
+                r#"
+INSERT OR REPLACE INTO resource_labels (resource, key, value) 
+VALUES ($1, $2, $3)
+"#,
+                uuid,
+                key,
+                value,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
 
-        for AccountDelta {
-            account,
-            debit,
-            credit,
-        } in deltas
-        {
+        for (key, value) in annotations {
             sqlx::query!(
                 r#"
-INSERT INTO accounting (epoch, entry, cluster, namespace, resource, account, debit, credit) 
-VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+INSERT OR REPLACE INTO resource_annotations (resource, key, value) 
+VALUES ($1, $2, $3)
 "#,
-                epoch,
-                entry,
-                cluster,
-                namespace,
-                resource,
-                account,
-                debit,
-                credit,
+                uuid,
+                key,
+                value,
             )
-            .execute(&mut *tx)
+            .execute(&mut **tx)
             .await?;
         }
 
         Ok(())
     }
 
-    pub async fn list_resources(&self, ns: &str) -> Result<Vec<ListResourceProj>> {
-        let rows = sqlx::query_as::<_, ListResourceProj>(
+    pub async fn get_resource_labels(&self, uuid: &[u8]) -> Result<BTreeMap<String, String>> {
+        let rows = sqlx::query_as::<_, (String, String)>(
             r#"
-SELECT name, uuid, kind FROM resources
-WHERE namespace = $1
+SELECT key, value FROM resource_labels
+WHERE resource = $1
 "#,
         )
-        .bind(ns)
+        .bind(uuid)
         .fetch_all(&self.db)
         .await?;
 
-        Ok(rows)
+        Ok(rows.into_iter().collect())
     }
 
-    pub async fn read_balance(&self, ns: &str) -> Result<Vec<(i64, i64, i64)>> {
-        let rows = sqlx::query_as::<_, (i64, i64, i64)>(
+    pub async fn get_resource_annotations(&self, uuid: &[u8]) -> Result<BTreeMap<String, String>> {
+        let rows = sqlx::query_as::<_, (String, String)>(
             r#"
-SELECT account, sum(debit), sum(credit) FROM accounting
-WHERE namespace = $1
-GROUP BY account
+SELECT key, value FROM resource_annotations
+WHERE resource = $1
 "#,
         )
-        .bind(ns)
+        .bind(uuid)
         .fetch_all(&self.db)
         .await?;
 
-        Ok(rows)
+        Ok(rows.into_iter().collect())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Records a revision of a resource, ignoring revisions that are already
+    /// recorded
+    pub async fn insert_resource_revision(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        revision: &ResourceRevision,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+INSERT OR IGNORE INTO resource_revisions (resource, revision, manifest, event_receipt, timestamp) 
+VALUES ($1, $2, $3, $4, $5)
+"#,
+            revision.resource,
+            revision.revision,
+            revision.manifest,
+            revision.event_receipt,
+            revision.timestamp,
+        )
+        .execute(&mut **tx)
+        .await?;
 
-    #[tokio::test]
-    async fn test_namespace_persistence() {
+        Ok(())
+    }
+
+    pub async fn list_resource_revisions(&self, uuid: &[u8]) -> Result<Vec<ResourceRevision>> {
+        let rows = sqlx::query_as::<_, ResourceRevision>(
+            r#"
+SELECT resource, revision, manifest, event_receipt, timestamp FROM resource_revisions
+WHERE resource = $1
+ORDER BY revision
+"#,
+        )
+        .bind(uuid)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn get_resource_revision(
+        &self,
+        uuid: &[u8],
+        revision: i64,
+    ) -> Result<Option<ResourceRevision>> {
+        let row = sqlx::query_as::<_, ResourceRevision>(
+            r#"
+SELECT resource, revision, manifest, event_receipt, timestamp FROM resource_revisions
+WHERE resource = $1 AND revision = $2
+"#,
+        )
+        .bind(uuid)
+        .bind(revision)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row)
+    }
+
+    pub async fn update_resource_status(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        uuid: &[u8],
+        status: &[u8],
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+UPDATE resources SET status = $3
+WHERE namespace = $1 AND uuid = $2
+"#,
+            ns,
+            uuid,
+            status,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_resource_by_name(
+        &self,
+        ns: &str,
+        kind: &str,
+        name: &str,
+    ) -> Result<Option<ResourceRow>> {
+        let row = sqlx::query_as::<_, ResourceRow>(
+            r#"
+SELECT uuid, namespace, name, kind, manifest, status, revision, deleting FROM resources
+WHERE namespace = $1 AND kind = $2 AND name = $3
+ORDER BY rowid
+LIMIT 1
+"#,
+        )
+        .bind(ns)
+        .bind(kind)
+        .bind(name)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row)
+    }
+
+    pub async fn get_resource_by_uuid(&self, ns: &str, uuid: &[u8]) -> Result<Option<ResourceRow>> {
+        let row = sqlx::query_as::<_, ResourceRow>(
+            r#"
+SELECT uuid, namespace, name, kind, manifest, status, revision, deleting FROM resources
+WHERE namespace = $1 AND uuid = $2
+"#,
+        )
+        .bind(ns)
+        .bind(uuid)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row)
+    }
+
+    /// Flags a resource as deleting and registers the finalizers that need to
+    /// clear before it can be removed
+    pub async fn mark_resource_deleting(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        ns: &str,
+        uuid: &[u8],
+        finalizers: &[String],
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+UPDATE resources SET deleting = TRUE
+WHERE namespace = $1 AND uuid = $2
+"#,
+            ns,
+            uuid,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        for finalizer in finalizers {
+            sqlx::query!(
+                r#"
+INSERT OR IGNORE INTO resource_finalizers (resource, finalizer) 
+VALUES ($1, $2)
+"#,
+                uuid,
+                finalizer,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove_resource_finalizer(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        uuid: &[u8],
+        finalizer: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+DELETE FROM resource_finalizers
+WHERE resource = $1 AND finalizer = $2
+"#,
+            uuid,
+            finalizer,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes a deleting resource once all of its finalizers have cleared.
+    /// Returns whether the row was removed.
+    pub async fn delete_resource_if_finalized(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        uuid: &[u8],
+    ) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+DELETE FROM resources
+WHERE uuid = $1 AND deleting AND NOT EXISTS (SELECT 1 FROM resource_finalizers WHERE resource = $1)
+"#,
+            uuid,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn insert_accounting(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        epoch: i64,
+        entry: &[u8],
+        cluster: &[u8],
+        namespace: &str,
+        resource: Option<&[u8]>,
+        deltas: Vec<AccountDelta>,
+    ) -> Result<()> {
+        for AccountDelta {
+            account,
+            debit,
+            credit,
+        } in deltas
+        {
+            sqlx::query!(
+                r#"
+INSERT INTO accounting (epoch, entry, cluster, namespace, resource, account, debit, credit) 
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+"#,
+                epoch,
+                entry,
+                cluster,
+                namespace,
+                resource,
+                account,
+                debit,
+                credit,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns up to `limit` resources of a namespace that pass the filter,
+    /// starting right after the `after` cursor
+    pub async fn list_resources(
+        &self,
+        ns: &str,
+        filter: &ResourceFilter,
+        after: Option<&ResourceCursor>,
+        limit: i64,
+    ) -> Result<Vec<ListResourceProj>> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT name, uuid, kind, deleting, manifest, status FROM resources WHERE namespace = ",
+        );
+
+        query.push_bind(ns);
+
+        if let Some(kind) = &filter.kind {
+            query.push(" AND kind = ");
+            query.push_bind(kind.clone());
+        }
+
+        if let Some(prefix) = &filter.name_prefix {
+            query.push(" AND substr(name, 1, length(");
+            query.push_bind(prefix.clone());
+            query.push(")) = ");
+            query.push_bind(prefix.clone());
+        }
+
+        if let Some(cursor) = after {
+            query.push(" AND (name, kind) > (");
+            query.push_bind(cursor.name.clone());
+            query.push(", ");
+            query.push_bind(cursor.kind.clone());
+            query.push(")");
+        }
+
+        for req in filter.selector.requirements() {
+            query.push(match req.operator {
+                LabelOperator::In | LabelOperator::Exists => " AND EXISTS",
+                LabelOperator::NotIn | LabelOperator::DoesNotExist => " AND NOT EXISTS",
+            });
+
+            query
+                .push(" (SELECT 1 FROM resource_labels WHERE resource = resources.uuid AND key = ");
+            query.push_bind(req.key.clone());
+
+            if !req.values.is_empty() {
+                query.push(" AND value IN (");
+
+                let mut values = query.separated(", ");
+                for value in req.values.iter() {
+                    values.push_bind(value.clone());
+                }
+
+                query.push(")");
+            }
+
+            query.push(")");
+        }
+
+        query.push(" ORDER BY name, kind LIMIT ");
+        query.push_bind(limit);
+
+        let rows = query
+            .build_query_as::<ListResourceProj>()
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(rows)
+    }
+
+    /// Records a signature as used, forgetting the ones with a timestamp
+    /// before `stale_before`. Returns false if it was already recorded.
+    pub async fn insert_seen_signature(
+        &self,
+        signature: &str,
+        timestamp: i64,
+        stale_before: i64,
+    ) -> Result<bool> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(
+            r#"
+DELETE FROM seen_signatures
+WHERE timestamp < $1
+"#,
+            stale_before,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query!(
+            r#"
+INSERT INTO seen_signatures (signature, timestamp)
+VALUES ($1, $2)
+ON CONFLICT (signature) DO NOTHING
+"#,
+            signature,
+            timestamp,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn insert_audit_record(&self, record: &AuditRecord) -> Result<()> {
+        sqlx::query!(
+            r#"
+INSERT INTO audit_log (timestamp, namespace, credential_kind, key_id, operation, resource_uuid, outcome, error) 
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+"#,
+            record.timestamp,
+            record.namespace,
+            record.credential_kind,
+            record.key_id,
+            record.operation,
+            record.resource_uuid,
+            record.outcome,
+            record.error,
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns a page of audit rows for a namespace, newest first, starting
+    /// right before the `before` id
+    pub async fn list_audit_records(
+        &self,
+        ns: &str,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<AuditRow>> {
+        let rows = sqlx::query_as::<_, AuditRow>(
+            r#"
+SELECT id, timestamp, namespace, credential_kind, key_id, operation, resource_uuid, outcome, error
+FROM audit_log
+WHERE namespace = $1 AND id < $2
+ORDER BY id DESC
+LIMIT $3
+"#,
+        )
+        .bind(ns)
+        .bind(before.unwrap_or(i64::MAX))
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn read_balance(&self, ns: &str) -> Result<Vec<(i64, i64, i64)>> {
+        let rows = sqlx::query_as::<_, (i64, i64, i64)>(
+            r#"
+SELECT account, COALESCE(sum(debit), 0), COALESCE(sum(credit), 0) FROM accounting
+WHERE namespace = $1
+GROUP BY account
+"#,
+        )
+        .bind(ns)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Sequence of the last event applied to the projections, 0 if none
+    pub async fn get_checkpoint(&self) -> Result<i64> {
+        let row = sqlx::query_as::<_, (i64,)>(
+            r#"
+SELECT sequence FROM projection_checkpoint
+WHERE id = 0
+"#,
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(row.map(|(x,)| x).unwrap_or_default())
+    }
+
+    /// Moves the checkpoint forward, it never goes back
+    pub async fn set_checkpoint(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        sequence: i64,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+INSERT INTO projection_checkpoint (id, sequence)
+VALUES (0, $1)
+ON CONFLICT (id) DO UPDATE SET sequence = MAX(sequence, excluded.sequence)
+"#,
+            sequence,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records that the event with the given receipt is reflected in the
+    /// projections, returns `false` if it already was
+    pub async fn mark_event_applied(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        receipt: &[u8],
+    ) -> Result<bool> {
+        let result = sqlx::query!(
+            r#"
+INSERT OR IGNORE INTO applied_events (receipt)
+VALUES ($1)
+"#,
+            receipt,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn insert_failed_event(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        receipt: &[u8],
+        sequence: i64,
+        error: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            r#"
+INSERT OR REPLACE INTO failed_events (receipt, sequence, error)
+VALUES ($1, $2, $3)
+"#,
+            receipt,
+            sequence,
+            error,
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_failed_events(&self) -> Result<Vec<FailedEvent>> {
+        let rows = sqlx::query_as::<_, FailedEvent>(
+            r#"
+SELECT receipt, sequence, error FROM failed_events
+ORDER BY sequence
+"#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn list_namespace_names(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query_as::<_, (String,)>(
+            r#"
+SELECT name FROM namespaces
+"#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(|(x,)| x).collect())
+    }
+
+    /// Namespace and id of every api key, including revoked ones
+    pub async fn list_api_key_ids(&self) -> Result<Vec<(String, Option<Vec<u8>>)>> {
+        let rows = sqlx::query_as::<_, (String, Option<Vec<u8>>)>(
+            r#"
+SELECT namespace, key_id FROM apikeys
+"#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    pub async fn list_member_keys(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let rows = sqlx::query_as::<_, (String, Vec<u8>)>(
+            r#"
+SELECT namespace, public_key FROM members
+"#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Uuid of every resource, including the ones that are deleting
+    pub async fn list_resource_uuids(&self) -> Result<Vec<Vec<u8>>> {
+        let rows = sqlx::query_as::<_, (Vec<u8>,)>(
+            r#"
+SELECT uuid FROM resources
+"#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(|(x,)| x).collect())
+    }
+
+    /// Cluster and entry of every accounting record
+    pub async fn list_accounting_entries(&self) -> Result<Vec<(Option<Vec<u8>>, Option<Vec<u8>>)>> {
+        let rows = sqlx::query_as::<_, (Option<Vec<u8>>, Option<Vec<u8>>)>(
+            r#"
+SELECT DISTINCT cluster, entry FROM accounting
+"#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Empties every table derived from events, along with the checkpoint.
+    /// The audit log and seen signatures are kept since they're recorded by
+    /// commands rather than events.
+    pub async fn reset_projections(&self) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        for table in PROJECTION_TABLES {
+            sqlx::query(&format!("DELETE FROM {table}"))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_namespace_persistence() {
         let db = FabricState::ephemeral().await.unwrap();
 
         db.migrate().await.unwrap();
 
         assert_eq!(db.namespace_exists("ns1").await.unwrap(), false);
 
-        db.insert_namespace("ns1").await.unwrap();
+        let mut tx = db.begin().await.unwrap();
+        assert!(db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap());
+        assert!(!db.insert_namespace(&mut tx, "ns1", b"key2").await.unwrap());
+        tx.commit().await.unwrap();
 
         assert_eq!(db.namespace_exists("ns1").await.unwrap(), true);
+
+        let key = db.get_namespace_root_key("ns1").await.unwrap();
+        assert_eq!(key.as_deref(), Some(b"key1".as_slice()));
+    }
+
+    fn hashing(m_cost: i64) -> ApiKeyHashing {
+        ApiKeyHashing {
+            algorithm: "argon2id".into(),
+            version: 19,
+            m_cost,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+
+    fn api_key(key_id: &[u8], digest: &[u8], salt: &[u8], m_cost: i64) -> ApiKey {
+        ApiKey {
+            key_id: Some(key_id.to_vec()),
+            digest: digest.to_vec(),
+            salt: salt.to_vec(),
+            hashing: hashing(m_cost),
+            validity: ApiKeyValidity::default(),
+            scopes: Some("resources:read".into()),
+            revoked: false,
+            legacy: false,
+        }
     }
 
     #[tokio::test]
@@ -227,12 +1238,20 @@ mod tests {
 
         db.migrate().await.unwrap();
 
-        db.insert_namespace("ns1").await.unwrap();
-        db.insert_api_key("ns1", b"0123", b"9876").await.unwrap();
-        db.insert_api_key("ns1", b"4567", b"5432").await.unwrap();
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+        db.insert_api_key(&mut tx, "ns1", &api_key(b"id1", b"0123", b"9876", 1024))
+            .await
+            .unwrap();
+        db.insert_api_key(&mut tx, "ns1", &api_key(b"id2", b"4567", b"5432", 2048))
+            .await
+            .unwrap();
 
-        db.insert_namespace("ns2").await.unwrap();
-        db.insert_api_key("ns2", b"abcd", b"zyxw").await.unwrap();
+        db.insert_namespace(&mut tx, "ns2", b"key2").await.unwrap();
+        db.insert_api_key(&mut tx, "ns2", &api_key(b"id1", b"abcd", b"zyxw", 1024))
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
 
         // TODO: don't fail if results are return in different order
         let mut keys = db.get_all_api_keys_for_namespace("ns1").await.unwrap();
@@ -240,15 +1259,559 @@ mod tests {
         let item = keys.remove(0);
         assert_eq!(item.digest, b"0123");
         assert_eq!(item.salt, b"9876");
+        assert_eq!(item.hashing, hashing(1024));
         let item = keys.remove(0);
         assert_eq!(item.digest, b"4567");
         assert_eq!(item.salt, b"5432");
+        assert_eq!(item.hashing, hashing(2048));
 
         let mut keys = db.get_all_api_keys_for_namespace("ns2").await.unwrap();
         assert_eq!(keys.len(), 1);
         let item = keys.remove(0);
         assert_eq!(item.digest, b"abcd");
         assert_eq!(item.salt, b"zyxw");
+
+        let item = db.get_api_key("ns1", b"id2").await.unwrap().unwrap();
+        assert_eq!(item.key_id.as_deref(), Some(b"id2".as_slice()));
+        assert_eq!(item.digest, b"4567");
+        assert_eq!(item.scopes.as_deref(), Some("resources:read"));
+
+        assert!(db.get_api_key("ns2", b"id2").await.unwrap().is_none());
+
+        assert!(!item.revoked);
+        let mut tx = db.begin().await.unwrap();
+        db.revoke_api_key(&mut tx, "ns1", b"id2").await.unwrap();
+        tx.commit().await.unwrap();
+        let item = db.get_api_key("ns1", b"id2").await.unwrap().unwrap();
+        assert!(item.revoked);
+
+        let item = db.get_api_key("ns1", b"id1").await.unwrap().unwrap();
+        assert!(!item.revoked);
+        assert_eq!(item.validity.not_after, None);
+
+        let mut tx = db.begin().await.unwrap();
+        db.update_api_key_expiry(&mut tx, "ns1", b"id1", 1000)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        let item = db.get_api_key("ns1", b"id1").await.unwrap().unwrap();
+        assert_eq!(item.validity.not_after, Some(1000));
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_api_key(
+            &mut tx,
+            "ns1",
+            &ApiKey {
+                legacy: true,
+                ..api_key(b"id3", b"89ab", b"1098", 1024)
+            },
+        )
+        .await
+        .unwrap();
+        tx.commit().await.unwrap();
+
+        let keys = db.get_live_legacy_api_keys("ns1", 500).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key_id.as_deref(), Some(b"id3".as_slice()));
+
+        let mut tx = db.begin().await.unwrap();
+        db.update_api_key_expiry(&mut tx, "ns1", b"id3", 1000)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        let keys = db.get_live_legacy_api_keys("ns1", 1000).await.unwrap();
+        assert!(keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_members_persistence() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+        db.insert_member(&mut tx, "ns1", b"alice", "admin")
+            .await
+            .unwrap();
+        db.insert_member(&mut tx, "ns1", b"bob", "viewer")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let role = db.get_member_role("ns1", b"alice").await.unwrap();
+        assert_eq!(role.as_deref(), Some("admin"));
+
+        let mut tx = db.begin().await.unwrap();
+        db.update_member_role(&mut tx, "ns1", b"bob", "developer")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        let role = db.get_member_role("ns1", b"bob").await.unwrap();
+        assert_eq!(role.as_deref(), Some("developer"));
+
+        let mut tx = db.begin().await.unwrap();
+        db.delete_member(&mut tx, "ns1", b"alice").await.unwrap();
+        tx.commit().await.unwrap();
+        assert!(db.get_member_role("ns1", b"alice").await.unwrap().is_none());
+        assert!(db.get_member_role("ns2", b"bob").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn resource_names_are_unique_unless_legacy() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+        db.insert_resource(&mut tx, "ns1", "pod", b"uuid1", "mypod", b"spec1")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let insert = |uuid: &'static [u8], legacy_duplicate: bool| {
+            sqlx::query(
+                r#"
+INSERT INTO resources (namespace, kind, uuid, name, manifest, legacy_duplicate)
+VALUES ('ns1', 'pod', $1, 'mypod', x'', $2)
+"#,
+            )
+            .bind(uuid)
+            .bind(legacy_duplicate)
+            .execute(&db.db)
+        };
+
+        // the database refuses new duplicates even when the projection doesn't
+        assert!(insert(b"uuid2", false).await.is_err());
+        assert!(insert(b"uuid3", true).await.is_ok());
+
+        let item = db
+            .get_resource_by_name("ns1", "pod", "mypod")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.uuid, b"uuid1");
+    }
+
+    #[tokio::test]
+    async fn test_resources_persistence() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+        db.insert_resource(&mut tx, "ns1", "pod", b"uuid1", "mypod", b"spec1")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        assert!(db.resource_exists("ns1", "pod", "mypod").await.unwrap());
+        assert!(!db.resource_exists("ns1", "svc", "mypod").await.unwrap());
+
+        let mut tx = db.begin().await.unwrap();
+        let duplicate = db
+            .insert_resource(&mut tx, "ns1", "pod", b"uuid2", "mypod", b"spec2")
+            .await
+            .unwrap();
+        assert!(!duplicate);
+
+        db.insert_rejected_resource(&mut tx, "ns1", "pod", b"uuid2", "mypod", b"receipt2")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert!(db.resource_was_rejected(b"uuid2").await.unwrap());
+        assert!(!db.resource_was_rejected(b"uuid1").await.unwrap());
+
+        let item = db
+            .get_resource_by_name("ns1", "pod", "mypod")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.uuid, b"uuid1");
+        assert_eq!(item.manifest, b"spec1");
+        assert!(item.status.is_none());
+
+        let mut tx = db.begin().await.unwrap();
+        db.update_resource_status(&mut tx, "ns1", b"uuid1", b"ready")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let item = db
+            .get_resource_by_uuid("ns1", b"uuid1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.name, "mypod");
+        assert_eq!(item.status.as_deref(), Some(b"ready".as_slice()));
+        assert_eq!(item.revision, 1);
+
+        let mut tx = db.begin().await.unwrap();
+        let updated = db
+            .update_resource_manifest(&mut tx, "ns1", b"uuid1", b"spec2", 2)
+            .await
+            .unwrap();
+        assert!(updated);
+
+        let stale = db
+            .update_resource_manifest(&mut tx, "ns1", b"uuid1", b"spec3", 2)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert!(!stale);
+
+        let item = db
+            .get_resource_by_uuid("ns1", b"uuid1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.manifest, b"spec2");
+        assert_eq!(item.revision, 2);
+
+        assert!(db
+            .get_resource_by_uuid("ns2", b"uuid1")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get_resource_by_name("ns1", "pod", "other")
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut tx = db.begin().await.unwrap();
+        let other_kind = db
+            .insert_resource(&mut tx, "ns1", "svc", b"uuid3", "mypod", b"spec3")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert!(other_kind);
+
+        let item = db
+            .get_resource_by_name("ns1", "svc", "mypod")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(item.uuid, b"uuid3");
+    }
+
+    #[tokio::test]
+    async fn test_resource_finalizers() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+        db.insert_resource(&mut tx, "ns1", "pod", b"uuid1", "mypod", b"")
+            .await
+            .unwrap();
+
+        assert!(!db
+            .delete_resource_if_finalized(&mut tx, b"uuid1")
+            .await
+            .unwrap());
+
+        let finalizers = vec!["a".to_string(), "b".to_string()];
+        db.mark_resource_deleting(&mut tx, "ns1", b"uuid1", &finalizers)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let items = db
+            .list_resources("ns1", &ResourceFilter::default(), None, 100)
+            .await
+            .unwrap();
+        assert!(items[0].deleting);
+
+        let mut tx = db.begin().await.unwrap();
+        db.remove_resource_finalizer(&mut tx, b"uuid1", "a")
+            .await
+            .unwrap();
+        assert!(!db
+            .delete_resource_if_finalized(&mut tx, b"uuid1")
+            .await
+            .unwrap());
+
+        db.remove_resource_finalizer(&mut tx, b"uuid1", "b")
+            .await
+            .unwrap();
+        assert!(db
+            .delete_resource_if_finalized(&mut tx, b"uuid1")
+            .await
+            .unwrap());
+        tx.commit().await.unwrap();
+
+        assert!(db
+            .list_resources("ns1", &ResourceFilter::default(), None, 100)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resource_labels() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        let pairs = [
+            ("web", "prod", "web"),
+            ("db", "prod", "db"),
+            ("dev", "dev", "web"),
+        ];
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+
+        for (name, env, tier) in pairs {
+            db.insert_resource(&mut tx, "ns1", "pod", name.as_bytes(), name, b"")
+                .await
+                .unwrap();
+
+            let labels = BTreeMap::from([("env".into(), env.into()), ("tier".into(), tier.into())]);
+            let annotations = BTreeMap::from([("owner".into(), "team a".into())]);
+
+            db.insert_resource_labels(&mut tx, name.as_bytes(), &labels, &annotations)
+                .await
+                .unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        let names = |selector: &str| {
+            let filter = ResourceFilter {
+                selector: selector.parse().unwrap(),
+                ..Default::default()
+            };
+
+            let db = &db;
+
+            async move {
+                let mut names: Vec<_> = db
+                    .list_resources("ns1", &filter, None, 100)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|x| x.name)
+                    .collect();
+
+                names.sort();
+                names
+            }
+        };
+
+        assert_eq!(names("").await, vec!["db", "dev", "web"]);
+        assert_eq!(names("env=prod,tier!=db").await, vec!["web"]);
+        assert_eq!(names("tier in (db,cache)").await, vec!["db"]);
+        assert_eq!(names("env notin (prod)").await, vec!["dev"]);
+        assert_eq!(names("!tier").await, Vec::<String>::new());
+
+        let labels = db.get_resource_labels(b"web").await.unwrap();
+        assert_eq!(labels.get("tier").map(String::as_str), Some("web"));
+
+        let annotations = db.get_resource_annotations(b"web").await.unwrap();
+        assert_eq!(annotations.get("owner").map(String::as_str), Some("team a"));
+
+        // labels go away along with their resource
+        let mut tx = db.begin().await.unwrap();
+        db.mark_resource_deleting(&mut tx, "ns1", b"web", &[])
+            .await
+            .unwrap();
+        assert!(db
+            .delete_resource_if_finalized(&mut tx, b"web")
+            .await
+            .unwrap());
+        tx.commit().await.unwrap();
+        assert!(db.get_resource_labels(b"web").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resources_paging() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+
+        for (kind, name) in [
+            ("svc", "web"),
+            ("pod", "web"),
+            ("pod", "db"),
+            ("pod", "worker"),
+        ] {
+            let uuid = format!("{kind}/{name}");
+            db.insert_resource(&mut tx, "ns1", kind, uuid.as_bytes(), name, b"")
+                .await
+                .unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        let all = ResourceFilter::default();
+
+        let page = db.list_resources("ns1", &all, None, 2).await.unwrap();
+        let names: Vec<_> = page
+            .iter()
+            .map(|x| (x.name.as_str(), x.kind.as_str()))
+            .collect();
+        assert_eq!(names, vec![("db", "pod"), ("web", "pod")]);
+
+        let cursor = ResourceCursor {
+            name: "web".into(),
+            kind: "pod".into(),
+        };
+
+        let page = db
+            .list_resources("ns1", &all, Some(&cursor), 2)
+            .await
+            .unwrap();
+        let names: Vec<_> = page
+            .iter()
+            .map(|x| (x.name.as_str(), x.kind.as_str()))
+            .collect();
+        assert_eq!(names, vec![("web", "svc"), ("worker", "pod")]);
+
+        let filter = ResourceFilter {
+            kind: Some("pod".into()),
+            name_prefix: Some("w".into()),
+            ..Default::default()
+        };
+
+        let page = db.list_resources("ns1", &filter, None, 10).await.unwrap();
+        let names: Vec<_> = page.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["web", "worker"]);
+    }
+
+    fn revision(uuid: &[u8], revision: i64, manifest: &[u8]) -> ResourceRevision {
+        ResourceRevision {
+            resource: uuid.to_vec(),
+            revision,
+            manifest: manifest.to_vec(),
+            event_receipt: Some(format!("rcpt{revision}").into_bytes()),
+            timestamp: Some(1000 + revision),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resource_revisions() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_resource_revision(&mut tx, &revision(b"uuid1", 2, b"spec2"))
+            .await
+            .unwrap();
+        db.insert_resource_revision(&mut tx, &revision(b"uuid1", 1, b"spec1"))
+            .await
+            .unwrap();
+        db.insert_resource_revision(&mut tx, &revision(b"uuid2", 1, b"other"))
+            .await
+            .unwrap();
+
+        // revisions are immutable, recording one again keeps the original
+        db.insert_resource_revision(&mut tx, &revision(b"uuid1", 1, b"changed"))
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let items = db.list_resource_revisions(b"uuid1").await.unwrap();
+        assert_eq!(
+            items,
+            vec![
+                revision(b"uuid1", 1, b"spec1"),
+                revision(b"uuid1", 2, b"spec2")
+            ]
+        );
+
+        let item = db.get_resource_revision(b"uuid1", 2).await.unwrap();
+        assert_eq!(item, Some(revision(b"uuid1", 2, b"spec2")));
+
+        assert!(db
+            .get_resource_revision(b"uuid1", 3)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    fn audit_record(ns: &str, operation: &str) -> AuditRecord {
+        AuditRecord {
+            timestamp: 1000,
+            namespace: ns.into(),
+            credential_kind: "api_key".into(),
+            key_id: Some(b"id1".to_vec()),
+            operation: operation.into(),
+            resource_uuid: None,
+            outcome: "success".into(),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn seen_signatures_are_single_use() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        assert!(db.insert_seen_signature("abcd", 1000, 700).await.unwrap());
+        assert!(!db.insert_seen_signature("abcd", 1000, 701).await.unwrap());
+        assert!(db.insert_seen_signature("ef01", 1000, 701).await.unwrap());
+
+        // forgotten once stale
+        assert!(db.insert_seen_signature("abcd", 1000, 1001).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_paging() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        for op in ["op1", "op2", "op3"] {
+            db.insert_audit_record(&audit_record("ns1", op))
+                .await
+                .unwrap();
+        }
+
+        db.insert_audit_record(&audit_record("ns2", "op4"))
+            .await
+            .unwrap();
+
+        let page = db.list_audit_records("ns1", None, 2).await.unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].record.operation, "op3");
+        assert_eq!(page[1].record.operation, "op2");
+
+        let page = db
+            .list_audit_records("ns1", Some(page[1].id), 2)
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].record, audit_record("ns1", "op1"));
+    }
+
+    #[tokio::test]
+    async fn test_projection_reset() {
+        let db = FabricState::ephemeral().await.unwrap();
+
+        assert_eq!(db.get_checkpoint().await.unwrap(), 0);
+
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
+        db.insert_resource(&mut tx, "ns1", "pod", b"resource1", "mypod", b"{}")
+            .await
+            .unwrap();
+        db.set_checkpoint(&mut tx, 2).await.unwrap();
+        db.set_checkpoint(&mut tx, 3).await.unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(db.get_checkpoint().await.unwrap(), 3);
+
+        db.insert_audit_record(&AuditRecord {
+            timestamp: 1,
+            namespace: "ns1".into(),
+            credential_kind: "apikey".into(),
+            key_id: None,
+            operation: "create_resource".into(),
+            resource_uuid: None,
+            outcome: "ok".into(),
+            error: None,
+        })
+        .await
+        .unwrap();
+
+        db.reset_projections().await.unwrap();
+
+        assert!(!db.namespace_exists("ns1").await.unwrap());
+        assert_eq!(db.get_checkpoint().await.unwrap(), 0);
+        assert_eq!(
+            db.list_audit_records("ns1", None, 10).await.unwrap().len(),
+            1
+        );
     }
 
     #[tokio::test]
@@ -257,13 +1820,15 @@ mod tests {
 
         db.migrate().await.unwrap();
 
-        db.insert_namespace("ns1").await.unwrap();
+        let mut tx = db.begin().await.unwrap();
+        db.insert_namespace(&mut tx, "ns1", b"key1").await.unwrap();
 
-        db.insert_resource("ns1", "pod", b"resource1", "mypod", b"")
+        db.insert_resource(&mut tx, "ns1", "pod", b"resource1", "mypod", b"")
             .await
             .unwrap();
 
         db.insert_accounting(
+            &mut tx,
             1,
             b"entry1",
             b"cluster1",
@@ -286,6 +1851,7 @@ mod tests {
         .unwrap();
 
         db.insert_accounting(
+            &mut tx,
             1,
             b"entry1",
             b"cluster1",
@@ -306,12 +1872,13 @@ mod tests {
         )
         .await
         .unwrap();
+        tx.commit().await.unwrap();
 
         let mut balance = db.read_balance("ns1").await.unwrap();
 
         print!("{:?}", balance);
 
         let entry1 = balance.remove(0);
-        assert_eq!(entry1, (1, 0, 0));
+        assert_eq!(entry1, (1, 800, 0));
     }
 }
@@ -1,16 +1,67 @@
 use anyhow::Result;
 use std::sync::Arc;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
 use tokio::sync::Mutex;
+use tracing::{error, warn};
 
-use crate::{domain::Domain, driven::event_dispatch::EventWrapper};
+use crate::domain::Domain;
+use crate::driven::event_dispatch::EventWrapper;
 
-pub async fn run(domain: Arc<Mutex<Domain>>) -> Result<()> {
-    let mut subscription = { domain.lock().await.event_dispatch.subscribe() };
+/// Projects the events coming through the subscription. It should be taken
+/// before the domain catches up with the log, so that nothing submitted in
+/// between slips past; events seen twice are skipped by their receipt.
+pub async fn run(
+    domain: Arc<Mutex<Domain>>,
+    mut subscription: Receiver<EventWrapper>,
+) -> Result<()> {
+    // set while events went unapplied, the log has to be caught up with
+    // before applying newer ones or the checkpoint would move past them. It
+    // starts out set in case catching up at startup failed.
+    let mut behind = domain.lock().await.event_dispatch.is_logged();
+
+    loop {
+        let event = match subscription.recv().await {
+            Ok(x) => Some(x),
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "fabric monitor lagged");
+                behind = true;
+                None
+            }
+            Err(RecvError::Closed) => break,
+        };
 
-    while let Ok(EventWrapper(evt, _)) = subscription.recv().await {
         let mut domain = domain.lock().await;
-        domain.handle(evt).await?;
+
+        if behind {
+            behind = !catch_up(&mut domain).await;
+        }
+
+        // while behind, the event is left for the next catch up
+        if let (Some(event), false) = (event, behind) {
+            if let Err(error) = domain.apply(event).await {
+                error!(?error, "fabric monitor failed to apply event");
+                behind = true;
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Applies the logged events the monitor missed, returning whether it can go
+/// on with the next ones
+async fn catch_up(domain: &mut Domain) -> bool {
+    if !domain.event_dispatch.is_logged() {
+        // nothing to recover them from, keep up with the next ones
+        error!("fabric monitor missed events that aren't logged");
+        return true;
+    }
+
+    match domain.catch_up().await {
+        Ok(_) => true,
+        Err(error) => {
+            error!(?error, "fabric monitor failed to catch up");
+            false
+        }
+    }
+}
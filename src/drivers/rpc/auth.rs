@@ -1,10 +1,22 @@
+use bech32::{ToBase32, Variant};
+use std::sync::Arc;
 use tonic::Status;
 use tracing::warn;
 
+use super::throttle::{Throttle, ThrottleKey};
 use crate::domain;
 
+const APIKEY_HRP: &str = "dmtr_apikey";
+
+const APIKEY_HEADER: &str = "dmtr-api-key";
+const SIGNATURE_HEADER: &str = "dmtr-signature";
+const TIMESTAMP_HEADER: &str = "dmtr-timestamp";
+const PUBLIC_KEY_HEADER: &str = "dmtr-public-key";
+
 #[derive(Clone)]
-pub struct Authenticator {}
+pub struct Authenticator {
+    throttle: Arc<Throttle>,
+}
 
 fn extract_required_metadata_string(
     request: &tonic::Request<()>,
@@ -19,29 +31,95 @@ fn extract_required_metadata_string(
         .map_err(|_| tonic::Status::unauthenticated("malformed auth value"))
 }
 
+/// Encodes an api key as a bech32m token with the key id as prefix of the
+/// payload
+pub fn encode_apikey(key_id: &[u8], secret: &[u8]) -> Result<String, bech32::Error> {
+    let payload = [key_id, secret].concat();
+    bech32::encode(APIKEY_HRP, payload.to_base32(), Variant::Bech32m)
+}
+
+fn decode_apikey(token: &str) -> Result<domain::Credential, tonic::Status> {
+    let (hrp, token, variant) = bech32::decode(token).map_err(|error| {
+        warn!(?error, "invalid bech32");
+        tonic::Status::permission_denied("invalid apikey")
+    })?;
+
+    if hrp != APIKEY_HRP {
+        warn!(hrp, "invalid bech32 hrp");
+        return Err(Status::permission_denied("invalid apikey"));
+    }
+
+    let mut token = bech32::convert_bits(&token, 5, 8, false).map_err(|error| {
+        warn!(?error, "invalid bech32");
+        tonic::Status::permission_denied("invalid apikey")
+    })?;
+
+    match variant {
+        // legacy tokens carry only the secret
+        Variant::Bech32 => Ok(domain::Credential::ApiKeyV1(token)),
+        Variant::Bech32m => {
+            if token.len() <= domain::KEY_ID_LENGTH {
+                warn!("apikey token too short");
+                return Err(Status::permission_denied("invalid apikey"));
+            }
+
+            let secret = token.split_off(domain::KEY_ID_LENGTH);
+            Ok(domain::Credential::ApiKeyV2(token, secret))
+        }
+    }
+}
+
+/// Builds a signature credential from the signature headers. Signatures are
+/// attributed to the namespace owner unless a member public key is sent along.
+fn decode_signature(request: &tonic::Request<()>) -> Result<domain::Credential, tonic::Status> {
+    let signature = extract_required_metadata_string(request, SIGNATURE_HEADER)?;
+
+    let timestamp = extract_required_metadata_string(request, TIMESTAMP_HEADER)?
+        .parse()
+        .map_err(|_| Status::unauthenticated("malformed auth timestamp"))?;
+
+    if !request.metadata().contains_key(PUBLIC_KEY_HEADER) {
+        return Ok(domain::Credential::OwnerSignatureV1(signature, timestamp));
+    }
+
+    let public_key = extract_required_metadata_string(request, PUBLIC_KEY_HEADER)?;
+    let public_key =
+        hex::decode(public_key).map_err(|_| Status::unauthenticated("malformed public key"))?;
+
+    Ok(domain::Credential::MemberSignatureV1(
+        public_key, signature, timestamp,
+    ))
+}
+
 impl tonic::service::Interceptor for Authenticator {
     fn call(
         &mut self,
         mut request: tonic::Request<()>,
     ) -> Result<tonic::Request<()>, tonic::Status> {
-        let token = extract_required_metadata_string(&request, "dmtr-api-key")?;
-
-        let (hrp, token, _) = bech32::decode(&token).map_err(|error| {
-            warn!(?error, "invalid bech32");
-            tonic::Status::permission_denied("invalid apikey")
-        })?;
-
-        if hrp != "dmtr_apikey" {
-            warn!(hrp, "invalid bech32 hrp");
-            return Err(Status::permission_denied("invalid apikey"));
+        if let Some(addr) = request.remote_addr() {
+            self.throttle.check(&ThrottleKey::Peer(addr.ip()))?;
         }
 
-        let token = bech32::convert_bits(&token, 5, 8, false).map_err(|error| {
-            warn!(?error, "invalid bech32");
-            tonic::Status::permission_denied("invalid apikey")
-        })?;
+        let has_apikey = request.metadata().contains_key(APIKEY_HEADER);
+        let has_signature = request.metadata().contains_key(SIGNATURE_HEADER);
 
-        let creds = domain::Credential::ApiKeyV1(token);
+        let creds = match (has_apikey, has_signature) {
+            (true, false) => {
+                let token = extract_required_metadata_string(&request, APIKEY_HEADER)?;
+                decode_apikey(&token)?
+            }
+            (false, true) => decode_signature(&request)?,
+            (true, true) => {
+                return Err(Status::unauthenticated(
+                    "ambiguous credentials, send either an api key or a signature",
+                ))
+            }
+            (false, false) => {
+                return Err(Status::unauthenticated(
+                    "missing credentials, send either an api key or a signature",
+                ))
+            }
+        };
 
         request.extensions_mut().insert(creds);
 
@@ -49,13 +127,85 @@ impl tonic::service::Interceptor for Authenticator {
     }
 }
 
-pub fn build_interceptor() -> Authenticator {
-    Authenticator {}
+/// Takes the credential that the interceptor attached to the request
+pub fn credential<T>(request: &tonic::Request<T>) -> Result<domain::Credential, tonic::Status> {
+    request
+        .extensions()
+        .get::<domain::Credential>()
+        .cloned()
+        .ok_or_else(|| Status::permission_denied("invalid credential"))
+}
+
+pub fn build_interceptor(throttle: Arc<Throttle>) -> Authenticator {
+    Authenticator { throttle }
 }
 
 #[cfg(test)]
 mod tests {
+    use tonic::service::Interceptor;
+
+    use super::*;
 
     #[tokio::test]
-    async fn build_token() {}
+    async fn build_token() {
+        let token = encode_apikey(b"keyid123", b"mybadpassword").unwrap();
+        assert!(token.starts_with("dmtr_apikey1"));
+
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert(APIKEY_HEADER, token.parse().unwrap());
+
+        let throttle = Arc::new(Throttle::new(Default::default()));
+        let request = build_interceptor(throttle).call(request).unwrap();
+
+        match request.extensions().get::<domain::Credential>() {
+            Some(domain::Credential::ApiKeyV2(key_id, secret)) => {
+                assert_eq!(key_id, b"keyid123");
+                assert_eq!(secret, b"mybadpassword");
+            }
+            _ => panic!("expected an api key credential"),
+        }
+    }
+
+    #[tokio::test]
+    async fn signature_headers() {
+        let throttle = Arc::new(Throttle::new(Default::default()));
+
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert(SIGNATURE_HEADER, "abcd".parse().unwrap());
+        request
+            .metadata_mut()
+            .insert(TIMESTAMP_HEADER, "1700000000".parse().unwrap());
+
+        let request = build_interceptor(throttle.clone()).call(request).unwrap();
+
+        match request.extensions().get::<domain::Credential>() {
+            Some(domain::Credential::OwnerSignatureV1(signature, timestamp)) => {
+                assert_eq!(signature, "abcd");
+                assert_eq!(*timestamp, 1700000000);
+            }
+            _ => panic!("expected an owner signature credential"),
+        }
+
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert(SIGNATURE_HEADER, "abcd".parse().unwrap());
+        request
+            .metadata_mut()
+            .insert(APIKEY_HEADER, "dmtr_apikey1xyz".parse().unwrap());
+
+        let status = build_interceptor(throttle.clone())
+            .call(request)
+            .unwrap_err();
+        assert!(status.message().contains("ambiguous"));
+
+        let status = build_interceptor(throttle)
+            .call(tonic::Request::new(()))
+            .unwrap_err();
+        assert!(status.message().contains("missing"));
+    }
 }
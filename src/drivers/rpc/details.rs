@@ -0,0 +1,86 @@
+use prost::Message;
+use tonic::{Code, Status};
+
+use crate::domain::FieldViolation;
+
+// mirrors of the google.rpc messages used for the richer error model, see
+// https://cloud.google.com/apis/design/errors#error_details
+
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    field_violations: Vec<BadRequestFieldViolation>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct BadRequestFieldViolation {
+    #[prost(string, tag = "1")]
+    field: String,
+    #[prost(string, tag = "2")]
+    description: String,
+}
+
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+/// Builds an `INVALID_ARGUMENT` status carrying a `google.rpc.BadRequest`
+/// detail with one entry per violated field
+pub fn bad_request(message: String, violations: &[FieldViolation]) -> Status {
+    let bad_request = BadRequest {
+        field_violations: violations
+            .iter()
+            .map(|x| BadRequestFieldViolation {
+                field: x.field.clone(),
+                description: x.description.clone(),
+            })
+            .collect(),
+    };
+
+    let status = RpcStatus {
+        code: Code::InvalidArgument as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: BAD_REQUEST_TYPE_URL.into(),
+            value: bad_request.encode_to_vec(),
+        }],
+    };
+
+    Status::with_details(
+        Code::InvalidArgument,
+        message,
+        status.encode_to_vec().into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn details_carry_field_violations() {
+        let status = bad_request(
+            "invalid manifest".into(),
+            &[FieldViolation {
+                field: "spec.replicas".into(),
+                description: "must be at least 0".into(),
+            }],
+        );
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let details = RpcStatus::decode(status.details()).unwrap();
+        assert_eq!(details.details[0].type_url, BAD_REQUEST_TYPE_URL);
+
+        let bad_request = BadRequest::decode(details.details[0].value.as_slice()).unwrap();
+        assert_eq!(bad_request.field_violations[0].field, "spec.replicas");
+    }
+}
@@ -9,11 +9,15 @@ use dmtri::demeter::ops::v1alpha::ops_service_server::OpsServiceServer;
 use crate::domain::Domain;
 
 pub mod auth;
+mod details;
 mod ops;
+pub mod throttle;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub listen_address: String,
+    #[serde(default)]
+    pub auth_throttle: throttle::ThrottleConfig,
 }
 
 pub async fn serve(config: Config, domain: Arc<Mutex<Domain>>) -> Result<()> {
@@ -25,8 +29,10 @@ pub async fn serve(config: Config, domain: Arc<Mutex<Domain>>) -> Result<()> {
         .build()
         .unwrap();
 
-    let inner = ops::OpsServiceImpl::new(domain);
-    let auth = auth::build_interceptor();
+    let throttle = Arc::new(throttle::Throttle::new(config.auth_throttle));
+
+    let inner = ops::OpsServiceImpl::new(domain, throttle.clone());
+    let auth = auth::build_interceptor(throttle);
 
     let server = OpsServiceServer::with_interceptor(inner, auth);
 
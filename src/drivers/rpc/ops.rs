@@ -1,17 +1,107 @@
+use futures::stream::BoxStream;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tonic::{async_trait, Status};
 
+use super::throttle::{Throttle, ThrottleKey};
+use super::{auth, details};
 use crate::domain;
+use crate::driven::event_dispatch::{ResumeFrom, ResyncRequired};
 use dmtri::demeter::ops::v1alpha as proto;
 
 pub struct OpsServiceImpl {
     domain: Arc<Mutex<domain::Domain>>,
+    throttle: Arc<Throttle>,
 }
 
 impl OpsServiceImpl {
-    pub fn new(domain: Arc<Mutex<domain::Domain>>) -> Self {
-        Self { domain }
+    pub fn new(domain: Arc<Mutex<domain::Domain>>, throttle: Arc<Throttle>) -> Self {
+        Self { domain, throttle }
+    }
+
+    fn check_throttle(&self, namespace: &str) -> Result<(), Status> {
+        self.throttle
+            .check(&ThrottleKey::Namespace(namespace.to_owned()))
+    }
+
+    /// Maps a domain error into a status, counting failed authentications
+    /// against both the peer and the namespace
+    fn domain_error(
+        &self,
+        peer: Option<SocketAddr>,
+        namespace: &str,
+        err: anyhow::Error,
+    ) -> Status {
+        if !err.is::<domain::InvalidCredentials>() {
+            return error_status(err);
+        }
+
+        if let Some(peer) = peer {
+            self.throttle.record_failure(ThrottleKey::Peer(peer.ip()));
+        }
+
+        self.throttle
+            .record_failure(ThrottleKey::Namespace(namespace.to_owned()));
+
+        Status::unauthenticated(err.to_string())
+    }
+}
+
+/// Maps the errors that don't involve credentials into a status
+fn error_status(err: anyhow::Error) -> Status {
+    if err.is::<domain::ResourceNotFound>() {
+        return Status::not_found(err.to_string());
+    }
+
+    if err.is::<domain::ResourceAlreadyExists>() {
+        return Status::already_exists(err.to_string());
+    }
+
+    if let Some(x) = err.downcast_ref::<domain::InvalidManifest>() {
+        return details::bad_request(err.to_string(), &x.0);
+    }
+
+    if err.is::<domain::InvalidArgument>() {
+        return Status::invalid_argument(err.to_string());
+    }
+
+    if err.is::<domain::PermissionDenied>() {
+        return Status::permission_denied(err.to_string());
+    }
+
+    // watchers are expected to list again and resume from there
+    if err.is::<ResyncRequired>() {
+        return Status::aborted(err.to_string());
+    }
+
+    Status::unknown(err.to_string())
+}
+
+fn proto_resource(
+    metadata: domain::ResourceMetadataV1,
+    spec: Option<domain::Blob>,
+    status: Option<domain::Blob>,
+    state: domain::ResourceState,
+) -> proto::Resource {
+    // the kind doubles as the type of the spec and status payloads
+    let any = |value: domain::Blob| proto::Any {
+        type_url: metadata.kind.clone(),
+        value: value.into(),
+    };
+
+    proto::Resource {
+        spec: spec.map(any),
+        status: status.map(any),
+        state: state.as_str().into(),
+        metadata: Some(proto::ResourceMetadata {
+            namespace: metadata.namespace,
+            name: metadata.name,
+            labels: metadata.labels.into_iter().collect(),
+            annotations: metadata.annotations.into_iter().collect(),
+            uuid: metadata.uuid.into(),
+            kind: metadata.kind,
+        }),
     }
 }
 
@@ -21,13 +111,9 @@ impl proto::ops_service_server::OpsService for OpsServiceImpl {
         &self,
         request: tonic::Request<proto::CreateResourceRequest>,
     ) -> Result<tonic::Response<proto::CreateResourceResponse>, tonic::Status> {
-        let credential = request.extensions().get::<domain::Credential>();
-
-        let credential = match credential {
-            None => return Err(Status::permission_denied("invalid credential")),
-            Some(x) => x.clone(),
-        };
+        let credential = auth::credential(&request)?;
 
+        let peer = request.remote_addr();
         let req = request.into_inner();
 
         let mut domain = self.domain.lock().await;
@@ -38,16 +124,21 @@ impl proto::ops_service_server::OpsService for OpsServiceImpl {
 
         let proto_spec = req.spec.ok_or(Status::invalid_argument("missing spec"))?;
 
+        let namespace = proto_meta.namespace;
+        self.check_throttle(&namespace)?;
+
         let ack = domain
             .create_resource(domain::CreateResourceCmd {
                 auth: credential,
-                namespace: proto_meta.namespace,
+                namespace: namespace.clone(),
                 name: proto_meta.name,
                 kind: proto_spec.type_url,
                 spec: proto_spec.value.into(),
+                labels: proto_meta.labels.into_iter().collect(),
+                annotations: proto_meta.annotations.into_iter().collect(),
             })
             .await
-            .map_err(|err| Status::unknown(err.to_string()))?;
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
 
         let res = proto::CreateResourceResponse {
             event_receipt: ack.event_receipt.into(),
@@ -61,57 +152,431 @@ impl proto::ops_service_server::OpsService for OpsServiceImpl {
         &self,
         request: tonic::Request<proto::ListResourcesRequest>,
     ) -> Result<tonic::Response<proto::ListResourcesResponse>, tonic::Status> {
-        let credential = request.extensions().get::<domain::Credential>();
+        let credential = auth::credential(&request)?;
 
-        let credential = match credential {
-            None => return Err(Status::permission_denied("invalid credential")),
-            Some(x) => x.clone(),
-        };
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        let namespace = req.namespace;
+        self.check_throttle(&namespace)?;
 
         let domain = self.domain.lock().await;
 
-        let items = domain
+        let page_token = match req.page_token.is_empty() {
+            true => None,
+            false => Some(req.page_token),
+        };
+
+        let out = domain
             .list_resources(domain::ListResourcesQuery {
                 auth: credential,
-                namespace_name: request.into_inner().namespace,
-                resource_name: "".into(),
+                namespace_name: namespace.clone(),
+                name_prefix: req.name_prefix,
+                kind: req.kind,
+                label_selector: req.label_selector,
+                page_size: req.page_size,
+                page_token,
+                include_spec: req.include_spec,
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
+
+        let items = out
+            .items
+            .into_iter()
+            .map(|x| proto_resource(x.metadata, x.spec, x.status, x.state))
+            .collect();
+
+        let res = proto::ListResourcesResponse {
+            items,
+            next_page_token: out.next_page_token.unwrap_or_default(),
+        };
+
+        Ok(tonic::Response::new(res))
+    }
+
+    async fn revoke_api_key(
+        &self,
+        request: tonic::Request<proto::RevokeApiKeyRequest>,
+    ) -> Result<tonic::Response<proto::RevokeApiKeyResponse>, tonic::Status> {
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+        self.check_throttle(&req.namespace)?;
+
+        let mut domain = self.domain.lock().await;
+
+        let ack = domain
+            .revoke_apikey(domain::RevokeApiKeyCmd {
+                auth: credential,
+                namespace: req.namespace.clone(),
+                key_id: req.key_id.into(),
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &req.namespace, err))?;
+
+        let res = proto::RevokeApiKeyResponse {
+            event_receipt: ack.event_receipt.into(),
+        };
+
+        Ok(tonic::Response::new(res))
+    }
+
+    async fn read_audit_log(
+        &self,
+        request: tonic::Request<proto::ReadAuditLogRequest>,
+    ) -> Result<tonic::Response<proto::ReadAuditLogResponse>, tonic::Status> {
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+        self.check_throttle(&req.namespace)?;
+
+        let before = match req.page_token.as_str() {
+            "" => None,
+            x => Some(
+                x.parse()
+                    .map_err(|_| Status::invalid_argument("invalid page token"))?,
+            ),
+        };
+
+        let domain = self.domain.lock().await;
+
+        let out = domain
+            .read_audit_log(domain::ReadAuditLogQuery {
+                auth: credential,
+                namespace_name: req.namespace.clone(),
+                before,
+                page_size: req.page_size,
             })
             .await
-            .map_err(|err| Status::unknown(err.to_string()))?
+            .map_err(|err| self.domain_error(peer, &req.namespace, err))?;
+
+        let records = out
+            .records
             .into_iter()
-            .map(|x| proto::Resource {
-                metadata: Some(proto::ResourceMetadata {
-                    namespace: x.metadata.namespace,
-                    name: x.metadata.name,
-                }),
-                spec: None,
-                status: None,
+            .map(|x| proto::AuditRecord {
+                timestamp: x.timestamp as u64,
+                credential_kind: x.credential_kind,
+                key_id: x.key_id.unwrap_or_default().into(),
+                operation: x.operation,
+                resource_uuid: x.resource_uuid.unwrap_or_default().into(),
+                outcome: x.outcome,
+                error: x.error.unwrap_or_default(),
             })
             .collect();
 
-        let res = proto::ListResourcesResponse { items };
+        let res = proto::ReadAuditLogResponse {
+            records,
+            next_page_token: out.next_cursor.map(|x| x.to_string()).unwrap_or_default(),
+        };
 
         Ok(tonic::Response::new(res))
     }
 
     async fn read_resource(
         &self,
-        _request: tonic::Request<proto::ReadResourceRequest>,
+        request: tonic::Request<proto::ReadResourceRequest>,
     ) -> Result<tonic::Response<proto::ReadResourceResponse>, tonic::Status> {
-        todo!()
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        let proto_meta = req
+            .metadata
+            .ok_or(Status::invalid_argument("missing metadata"))?;
+
+        // the uuid takes precedence when both are present
+        let resource = match req.uuid.is_empty() {
+            true => domain::ResourceRef::Name(proto_meta.kind, proto_meta.name),
+            false => domain::ResourceRef::Uuid(req.uuid.into()),
+        };
+
+        let namespace = proto_meta.namespace;
+        self.check_throttle(&namespace)?;
+
+        let domain = self.domain.lock().await;
+
+        let out = domain
+            .read_resource(domain::ReadResourceQuery {
+                auth: credential,
+                namespace_name: namespace.clone(),
+                resource,
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
+
+        let resource = proto_resource(out.metadata, Some(out.spec), out.status, out.state);
+
+        let res = proto::ReadResourceResponse {
+            resource: Some(resource),
+        };
+
+        Ok(tonic::Response::new(res))
     }
 
     async fn patch_resource(
         &self,
-        _request: tonic::Request<proto::PatchResourceRequest>,
+        request: tonic::Request<proto::PatchResourceRequest>,
     ) -> Result<tonic::Response<proto::PatchResourceResponse>, tonic::Status> {
-        todo!()
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        let proto_meta = req
+            .metadata
+            .ok_or(Status::invalid_argument("missing metadata"))?;
+
+        let patch_kind = req
+            .patch_type
+            .parse()
+            .map_err(|err: anyhow::Error| Status::invalid_argument(err.to_string()))?;
+
+        let namespace = proto_meta.namespace;
+        self.check_throttle(&namespace)?;
+
+        let mut domain = self.domain.lock().await;
+
+        let ack = domain
+            .patch_resource(domain::PatchResourceCmd {
+                auth: credential,
+                namespace: namespace.clone(),
+                resource: domain::ResourceRef::Name(proto_meta.kind, proto_meta.name),
+                patch_kind,
+                patch: req.patch.into(),
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
+
+        let res = proto::PatchResourceResponse {
+            event_receipt: ack.event_receipt.into(),
+            revision: ack.revision,
+        };
+
+        Ok(tonic::Response::new(res))
     }
 
     async fn delete_resource(
         &self,
-        _request: tonic::Request<proto::DeleteResourceRequest>,
+        request: tonic::Request<proto::DeleteResourceRequest>,
     ) -> Result<tonic::Response<proto::DeleteResourceResponse>, tonic::Status> {
-        todo!()
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        let proto_meta = req
+            .metadata
+            .ok_or(Status::invalid_argument("missing metadata"))?;
+
+        let namespace = proto_meta.namespace;
+        self.check_throttle(&namespace)?;
+
+        let mut domain = self.domain.lock().await;
+
+        let ack = domain
+            .delete_resource(domain::DeleteResourceCmd {
+                auth: credential,
+                namespace: namespace.clone(),
+                resource: domain::ResourceRef::Name(proto_meta.kind, proto_meta.name),
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
+
+        let res = proto::DeleteResourceResponse {
+            event_receipt: ack.event_receipt.into(),
+        };
+
+        Ok(tonic::Response::new(res))
+    }
+
+    async fn list_resource_revisions(
+        &self,
+        request: tonic::Request<proto::ListResourceRevisionsRequest>,
+    ) -> Result<tonic::Response<proto::ListResourceRevisionsResponse>, tonic::Status> {
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        let proto_meta = req
+            .metadata
+            .ok_or(Status::invalid_argument("missing metadata"))?;
+
+        let namespace = proto_meta.namespace;
+        self.check_throttle(&namespace)?;
+
+        let domain = self.domain.lock().await;
+
+        let revisions = domain
+            .list_resource_revisions(domain::ListResourceRevisionsQuery {
+                auth: credential,
+                namespace_name: namespace.clone(),
+                resource: domain::ResourceRef::Name(proto_meta.kind, proto_meta.name),
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?
+            .into_iter()
+            .map(|x| proto::ResourceRevision {
+                revision: x.revision,
+                spec: x.spec.into(),
+                event_receipt: x.event_receipt.unwrap_or_default().into(),
+                timestamp: x.timestamp.unwrap_or_default(),
+            })
+            .collect();
+
+        let res = proto::ListResourceRevisionsResponse { revisions };
+
+        Ok(tonic::Response::new(res))
+    }
+
+    async fn diff_resource_revisions(
+        &self,
+        request: tonic::Request<proto::DiffResourceRevisionsRequest>,
+    ) -> Result<tonic::Response<proto::DiffResourceRevisionsResponse>, tonic::Status> {
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        let proto_meta = req
+            .metadata
+            .ok_or(Status::invalid_argument("missing metadata"))?;
+
+        let namespace = proto_meta.namespace;
+        self.check_throttle(&namespace)?;
+
+        let domain = self.domain.lock().await;
+
+        let out = domain
+            .diff_resource_revisions(domain::DiffResourceRevisionsQuery {
+                auth: credential,
+                namespace_name: namespace.clone(),
+                resource: domain::ResourceRef::Name(proto_meta.kind, proto_meta.name),
+                from: req.from_revision,
+                to: req.to_revision,
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
+
+        let res = proto::DiffResourceRevisionsResponse {
+            patch: out.patch.into(),
+        };
+
+        Ok(tonic::Response::new(res))
+    }
+
+    async fn rollback_resource(
+        &self,
+        request: tonic::Request<proto::RollbackResourceRequest>,
+    ) -> Result<tonic::Response<proto::RollbackResourceResponse>, tonic::Status> {
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        let proto_meta = req
+            .metadata
+            .ok_or(Status::invalid_argument("missing metadata"))?;
+
+        let namespace = proto_meta.namespace;
+        self.check_throttle(&namespace)?;
+
+        let mut domain = self.domain.lock().await;
+
+        let ack = domain
+            .rollback_resource(domain::RollbackResourceCmd {
+                auth: credential,
+                namespace: namespace.clone(),
+                resource: domain::ResourceRef::Name(proto_meta.kind, proto_meta.name),
+                revision: req.revision,
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
+
+        let res = proto::RollbackResourceResponse {
+            event_receipt: ack.event_receipt.into(),
+            revision: ack.revision,
+        };
+
+        Ok(tonic::Response::new(res))
+    }
+
+    type WatchResourcesStream = BoxStream<'static, Result<proto::WatchResourcesResponse, Status>>;
+
+    async fn watch_resources(
+        &self,
+        request: tonic::Request<proto::WatchResourcesRequest>,
+    ) -> Result<tonic::Response<Self::WatchResourcesStream>, tonic::Status> {
+        let credential = auth::credential(&request)?;
+
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+
+        // the receipt takes precedence when both are present. Without either
+        // the watch starts from now, while a sequence of 0 replays the whole
+        // log.
+        let from = match (req.after_receipt.is_empty(), req.after_sequence) {
+            (false, _) => ResumeFrom::Receipt(req.after_receipt.into()),
+            (true, Some(x)) => ResumeFrom::Sequence(x),
+            (true, None) => ResumeFrom::Now,
+        };
+
+        let namespace = req.namespace;
+        self.check_throttle(&namespace)?;
+
+        let watch = self
+            .domain
+            .lock()
+            .await
+            .watch_resources(domain::WatchResourcesQuery {
+                auth: credential,
+                namespace_name: namespace.clone(),
+                from,
+            })
+            .await
+            .map_err(|err| self.domain_error(peer, &namespace, err))?;
+
+        // the stream ends after the first error, which is the last item sent
+        let stream = futures::stream::unfold(Some(watch), |watch| async move {
+            let mut watch = watch?;
+
+            match watch.next().await {
+                Ok(Some(x)) => {
+                    let res = proto::WatchResourcesResponse {
+                        event_type: x.kind.as_str().into(),
+                        sequence: x.sequence,
+                        event_receipt: x.event_receipt.into(),
+                        resource: Some(proto_resource(x.metadata, x.spec, x.status, x.state)),
+                    };
+
+                    Some((Ok(res), Some(watch)))
+                }
+                Ok(None) => None,
+                Err(err) => Some((Err(error_status(err)), None)),
+            }
+        });
+
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    #[test]
+    fn scope_denials_map_to_permission_denied() {
+        let key = domain::Principal::ApiKey(Some(b"id".to_vec()), Some("billing:read".into()));
+        let err = key.authorize(domain::Scope::ResourcesWrite).unwrap_err();
+
+        let status = error_status(err);
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert!(status.message().contains("resources:write"));
     }
 }
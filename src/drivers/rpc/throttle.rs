@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Status};
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThrottleConfig {
+    /// Failed authentications tolerated within a window before locking out a
+    /// peer
+    pub max_failures: u32,
+    /// Failed authentications tolerated within a window before locking out a
+    /// namespace, counted across all peers. Higher than the peer threshold,
+    /// since reaching it locks out the legitimate clients of the namespace too
+    #[serde(default = "default_max_namespace_failures")]
+    pub max_namespace_failures: u32,
+    /// Seconds over which failures are counted
+    pub window_secs: u64,
+    /// Seconds that a locked out key has to wait
+    pub lockout_secs: u64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 10,
+            max_namespace_failures: default_max_namespace_failures(),
+            window_secs: 60,
+            lockout_secs: 300,
+        }
+    }
+}
+
+fn default_max_namespace_failures() -> u32 {
+    100
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ThrottleKey {
+    Peer(IpAddr),
+    Namespace(String),
+}
+
+impl ThrottleKey {
+    fn max_failures(&self, config: &ThrottleConfig) -> u32 {
+        match self {
+            ThrottleKey::Peer(_) => config.max_failures,
+            ThrottleKey::Namespace(_) => config.max_namespace_failures,
+        }
+    }
+}
+
+struct Entry {
+    window_start: Instant,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed authentications and locks out the offending keys once they go
+/// over the configured threshold
+pub struct Throttle {
+    config: ThrottleConfig,
+    entries: Mutex<HashMap<ThrottleKey, Entry>>,
+}
+
+impl Throttle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns the time left until the key can retry, if it's locked out
+    pub fn locked_for(&self, key: &ThrottleKey) -> Option<Duration> {
+        let entries = self.entries.lock().unwrap();
+        let locked_until = entries.get(key)?.locked_until?;
+
+        locked_until.checked_duration_since(Instant::now())
+    }
+
+    pub fn record_failure(&self, key: ThrottleKey) {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window_secs);
+
+        let mut entries = self.entries.lock().unwrap();
+
+        // forget keys that are neither locked out nor within a counting window
+        entries.retain(|_, x| {
+            x.locked_until.is_some_and(|until| until > now)
+                || now.duration_since(x.window_start) <= window
+        });
+
+        let entry = entries.entry(key.clone()).or_insert(Entry {
+            window_start: now,
+            failures: 0,
+            locked_until: None,
+        });
+
+        if now.duration_since(entry.window_start) > window {
+            entry.window_start = now;
+            entry.failures = 0;
+        }
+
+        entry.failures += 1;
+
+        if entry.failures >= key.max_failures(&self.config) {
+            let lockout = Duration::from_secs(self.config.lockout_secs);
+            warn!(
+                ?key,
+                lockout_secs = lockout.as_secs(),
+                "locking out after auth failures"
+            );
+
+            entry.locked_until = Some(now + lockout);
+            entry.window_start = now;
+            entry.failures = 0;
+        }
+    }
+
+    /// Fails with `RESOURCE_EXHAUSTED` if the key is currently locked out
+    pub fn check(&self, key: &ThrottleKey) -> Result<(), Status> {
+        match self.locked_for(key) {
+            Some(retry_after) => Err(exhausted(retry_after)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn exhausted(retry_after: Duration) -> Status {
+    // round up so that clients never retry before the lockout ends
+    let secs = retry_after.as_secs() + 1;
+
+    let mut metadata = MetadataMap::new();
+    metadata.insert("retry-after", secs.into());
+
+    Status::with_metadata(
+        Code::ResourceExhausted,
+        format!("too many failed authentications, retry after {secs}s"),
+        metadata,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_out_after_threshold() {
+        let throttle = Throttle::new(ThrottleConfig {
+            max_failures: 3,
+            max_namespace_failures: 5,
+            window_secs: 60,
+            lockout_secs: 60,
+        });
+
+        let peer = ThrottleKey::Peer("10.0.0.1".parse().unwrap());
+        let ns = ThrottleKey::Namespace("ns1".into());
+
+        throttle.record_failure(peer.clone());
+        throttle.record_failure(peer.clone());
+        assert!(throttle.check(&peer).is_ok());
+
+        throttle.record_failure(peer.clone());
+
+        let status = throttle.check(&peer).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.metadata().get("retry-after").is_some());
+
+        assert!(throttle.check(&ns).is_ok());
+    }
+
+    #[test]
+    fn namespaces_take_failures_from_every_peer() {
+        let throttle = Throttle::new(ThrottleConfig {
+            max_failures: 3,
+            max_namespace_failures: 5,
+            window_secs: 60,
+            lockout_secs: 60,
+        });
+
+        let ns = ThrottleKey::Namespace("ns1".into());
+
+        // as recorded by the rpc service, each peer staying under its own
+        // threshold while the namespace goes over its own
+        for ip in ["10.0.0.1", "10.0.0.2", "10.0.0.3"] {
+            assert!(throttle.check(&ns).is_ok());
+
+            let peer = ThrottleKey::Peer(ip.parse().unwrap());
+
+            for _ in 0..2 {
+                throttle.record_failure(peer.clone());
+                throttle.record_failure(ns.clone());
+            }
+
+            assert!(throttle.check(&peer).is_ok());
+        }
+
+        let status = throttle.check(&ns).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(throttle
+            .check(&ThrottleKey::Namespace("ns2".into()))
+            .is_ok());
+    }
+}